@@ -1,4 +1,4 @@
-use saftbar::bar::{Alignment, Bar, ColoredText};
+use saftbar::bar::{Alignment, Bar, ContentItem, Event, ItemLocation, PopupPlacement};
 
 fn render(bar: &mut Bar) {
     let red = (255, 0, 0, 255);
@@ -8,104 +8,60 @@ fn render(bar: &mut Bar) {
     let green = (0, 255, 0, 255);
 
     bar.clear_monitors();
-    bar.render_string(
+    bar.draw(
         0,
         Alignment::Left,
         &[
-            ColoredText {
-                text: "".to_owned(),
-                fg: white,
-                bg: red,
-            },
-            ColoredText {
-                text: "t s g g s y j p g a g         ".to_owned(),
-                fg: red,
-                bg: white,
-            },
-            ColoredText {
-                text: "".to_owned(),
-                fg: white,
-                bg: red,
-            },
-            ColoredText {
-                text: "leftlast1".to_owned(),
-                fg: black,
-                bg: blue,
-            },
+            ContentItem::text("hover me")
+                .bg(red)
+                .hover_bg(blue)
+                .pad(8)
+                .on_click("popup"),
+            ContentItem::text("or me")
+                .fg(black)
+                .bg(white)
+                .hover_fg(red)
+                .pad(8)
+                .underline()
+                .on_click("popup"),
+            ContentItem::text("not me").bg(black).pad(8),
+            ContentItem::text("quit")
+                .bg(red)
+                .hover_bg(blue)
+                .pad(8)
+                .on_click("quit"),
         ],
     );
 
-    bar.render_string(
-        0,
+    // Like a clock or a tray, the hint is only shown on the primary monitor.
+    let primary = bar.primary_monitor().unwrap_or(0);
+    bar.draw(
+        primary,
         Alignment::Right,
-        &[
-            ColoredText {
-                text: "rightfirst1".to_owned(),
-                fg: green,
-                bg: red,
-            },
-            ColoredText {
-                text: "rightlast1".to_owned(),
-                fg: white,
-                bg: black,
-            },
-        ],
-    );
-
-    bar.render_string(
-        1,
-        Alignment::Left,
-        &[
-            ColoredText {
-                text: "tsggsyjpgagOQIWUOEIRJSLKN<VMCXNV".to_owned(),
-                fg: red,
-                bg: white,
-            },
-            ColoredText {
-                text: "white black".to_owned(),
-                fg: white,
-                bg: black,
-            },
-            ColoredText {
-                text: "white red".to_owned(),
-                fg: white,
-                bg: red,
-            },
-            ColoredText {
-                text: "white blue".to_owned(),
-                fg: white,
-                bg: blue,
-            },
-            ColoredText {
-                text: "white green".to_owned(),
-                fg: white,
-                bg: green,
-            },
-        ],
+        &[ContentItem::text("Super+B to redraw")
+            .fg(black)
+            .bg(green)
+            .hover_fg(white)
+            .hover_bg(black)
+            .pad(8)],
     );
+}
 
-    bar.render_string(
-        1,
-        Alignment::Right,
-        &[
-            ColoredText {
-                text: "          ".to_owned(),
-                fg: white,
-                bg: red,
-            },
-            ColoredText {
-                text: "".to_owned(),
-                fg: green,
-                bg: white,
-            },
-        ],
-    );
+fn open_popup(bar: &mut Bar, anchor: ItemLocation) {
+    let rows = ["popup for", &format!("{anchor:?}")]
+        .map(|text| vec![ContentItem::from(format!(" {text} "))]);
+    bar.open_popup(anchor, PopupPlacement::Below, &rows);
 }
 
 #[tokio::main]
 async fn main() {
     // Connect to the Xserver and initialize scr
-    let mut bar = Bar::new();
+    let mut bar = Bar::new().await;
+    // `XK_b`.
+    let keysym_b = 0x62;
+    if !bar.grab_key(xcb::x::ModMask::N4, keysym_b).await {
+        println!("Failed to grab Super+B");
+    }
 
     let mut redraw = true;
 
@@ -113,15 +69,46 @@ async fn main() {
         if redraw {
             println!("Redraw!");
             render(&mut bar);
-            bar.blit();
+            bar.present();
             bar.flush();
             redraw = false;
         }
 
-        let event = bar.next_x_event().await;
-        println!("{:#?}", event);
-        if let xcb::Event::X(xcb::x::Event::KeyPress(_)) = event {
-            redraw = true
+        match bar.next_event().await {
+            Event::Hover(location) => println!("Hovering {location:?}"),
+            Event::Click {
+                location, action, ..
+            } => {
+                println!("Clicked {location:?}: {action}");
+                match action.as_str() {
+                    "quit" => {
+                        let white = (255, 255, 255, 255);
+                        let black = (0, 0, 0, 255);
+                        let placement = PopupPlacement::Below;
+                        bar.confirm(location, placement, "Quit?", "confirmed-quit", white, black);
+                    }
+                    "confirmed-quit" => {
+                        bar.shutdown();
+                        return;
+                    }
+                    _ => open_popup(&mut bar, location),
+                }
+            }
+            Event::Hotkey(hotkey) => {
+                println!("Pressed {hotkey:?}");
+                redraw = true;
+            }
+            Event::Reconnected => {
+                println!("Reconnected");
+                redraw = true;
+            }
+            Event::Close => {
+                println!("Shutting down");
+                bar.shutdown();
+                return;
+            }
+            Event::Tick => {}
+            Event::X(event) => println!("{event:#?}"),
         }
     }
 }
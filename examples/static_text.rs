@@ -1,16 +1,15 @@
 use saftbar::bar::{
-    Alignment, Bar, ContentItem, ContentShape, PowerlineDirection, PowerlineFill, PowerlineStyle,
+    Alignment, Bar, ContentItem, PowerlineDirection, PowerlineFill, PowerlineStyle,
 };
 use std::env;
 
-fn render_text(bar: &mut Bar, text: &String) {
+fn render_text(bar: &mut Bar, text: &str) {
     let red = (255, 0, 0, 255);
     let blue = (0, 0, 255, 255);
     let black = (0, 0, 0, 255);
     let white = (255, 255, 255, 255);
-    let green = (0, 255, 0, 255);
 
-    let shape = ContentShape::Powerline(
+    let separator = ContentItem::powerline(
         PowerlineStyle::Powerline,
         PowerlineFill::Full,
         PowerlineDirection::Right,
@@ -19,118 +18,70 @@ fn render_text(bar: &mut Bar, text: &String) {
         0,
         Alignment::Left,
         &[
-            ContentItem {
-                bg: red,
-                fg: black,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: red,
-                fg: white,
-                shape: ContentShape::Text(text.to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: blue,
-                fg: black,
-                shape: ContentShape::Text(text.to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::No,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::No,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: ContentShape::Text(text.to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::No,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::No,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: ContentShape::Text(text.to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: black,
-                shape: ContentShape::Text(text.to_owned()),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: shape.clone(),
-            },
+            separator.clone().fg(black).bg(red),
+            ContentItem::text(text).fg(white).bg(red),
+            separator.clone().fg(red).bg(blue),
+            ContentItem::text(text).fg(black).bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::No,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::No,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::Full,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text(text).fg(blue).bg(black),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::Full,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::No,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::No,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::Full,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text(text).fg(blue).bg(black),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::Full,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text(text).fg(black).bg(blue),
+            separator.clone().fg(blue).bg(black),
         ],
     );
 }
@@ -142,7 +93,7 @@ fn render(bar: &mut Bar) {
     let white = (255, 255, 255, 255);
     let green = (0, 255, 0, 255);
 
-    let shape = ContentShape::Powerline(
+    let separator = ContentItem::powerline(
         PowerlineStyle::Powerline,
         PowerlineFill::Full,
         PowerlineDirection::Right,
@@ -151,124 +102,76 @@ fn render(bar: &mut Bar) {
         0,
         Alignment::Left,
         &[
-            ContentItem {
-                bg: red,
-                fg: black,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: red,
-                fg: white,
-                shape: ContentShape::Text(
-                    "t s g g s y j󰌃 p m󰊫 a g         ".to_owned(),
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: blue,
-                fg: black,
-                shape: ContentShape::Text("leftlast1".to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::No,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::No,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: ContentShape::Text(" ".to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Octagon,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::No,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::No,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Left,
-                ),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: ContentShape::Text(" ".to_owned()),
-            },
-            ContentItem {
-                bg: blue,
-                fg: red,
-                shape: ContentShape::Powerline(
-                    PowerlineStyle::Powerline,
-                    PowerlineFill::Full,
-                    PowerlineDirection::Right,
-                ),
-            },
-            ContentItem {
-                bg: blue,
-                fg: black,
-                shape: ContentShape::Text("leftlast1a".to_owned()),
-            },
-            ContentItem {
-                bg: black,
-                fg: blue,
-                shape: shape.clone(),
-            },
+            separator.clone().fg(black).bg(red),
+            ContentItem::text("t s g g s y j󰌃 p m󰊫 a g         ".to_owned())
+                .fg(white)
+                .bg(red),
+            separator.clone().fg(red).bg(blue),
+            ContentItem::text("leftlast1").fg(black).bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::No,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::No,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::Full,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text(" ").fg(blue).bg(black),
+            ContentItem::powerline(
+                PowerlineStyle::Octagon,
+                PowerlineFill::Full,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::No,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::No,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::Full,
+                PowerlineDirection::Left,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text(" ").fg(blue).bg(black),
+            ContentItem::powerline(
+                PowerlineStyle::Powerline,
+                PowerlineFill::Full,
+                PowerlineDirection::Right,
+            )
+            .fg(red)
+            .bg(blue),
+            ContentItem::text("leftlast1a").fg(black).bg(blue),
+            separator.clone().fg(blue).bg(black),
         ],
     );
 
-    let shape = ContentShape::Powerline(
+    let separator = ContentItem::powerline(
         PowerlineStyle::Powerline,
         PowerlineFill::Full,
         PowerlineDirection::Left,
@@ -277,30 +180,14 @@ fn render(bar: &mut Bar) {
         0,
         Alignment::Right,
         &[
-            ContentItem {
-                bg: black,
-                fg: green,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: green,
-                fg: red,
-                shape: ContentShape::Text("rightfirst".to_owned()),
-            },
-            ContentItem {
-                bg: green,
-                fg: blue,
-                shape: ContentShape::Text("rightlast".to_owned()),
-            },
-            ContentItem {
-                bg: green,
-                fg: black,
-                shape: shape.clone(),
-            },
+            separator.clone().fg(green).bg(black),
+            ContentItem::text("rightfirst").fg(red).bg(green),
+            ContentItem::text("rightlast").fg(blue).bg(green),
+            separator.clone().fg(black).bg(green),
         ],
     );
 
-    let shape = ContentShape::Powerline(
+    let separator = ContentItem::powerline(
         PowerlineStyle::Octagon,
         PowerlineFill::Full,
         PowerlineDirection::Right,
@@ -309,47 +196,19 @@ fn render(bar: &mut Bar) {
         1,
         Alignment::Left,
         &[
-            ContentItem {
-                bg: white,
-                fg: black,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: white,
-                fg: black,
-                shape: ContentShape::Text(
-                    "tsggsyj󰌃pm󰊫agOQIWUOEIRJSLKN<VMCXNV".to_owned(),
-                ),
-            },
-            ContentItem {
-                bg: white,
-                fg: blue,
-                shape: ContentShape::Text("blue".to_owned()),
-            },
-            ContentItem {
-                bg: white,
-                fg: green,
-                shape: ContentShape::Text("green".to_owned()),
-            },
-            ContentItem {
-                bg: white,
-                fg: green,
-                shape: ContentShape::Text("green".to_owned()),
-            },
-            ContentItem {
-                bg: white,
-                fg: red,
-                shape: ContentShape::Text("red".to_owned()),
-            },
-            ContentItem {
-                bg: black,
-                fg: white,
-                shape: shape.clone(),
-            },
+            separator.clone().fg(black).bg(white),
+            ContentItem::text("tsggsyj󰌃pm󰊫agOQIWUOEIRJSLKN<VMCXNV".to_owned())
+                .fg(black)
+                .bg(white),
+            ContentItem::text("blue").fg(blue).bg(white),
+            ContentItem::text("green").fg(green).bg(white),
+            ContentItem::text("green").fg(green).bg(white),
+            ContentItem::text("red").fg(red).bg(white),
+            separator.clone().fg(white).bg(black),
         ],
     );
 
-    let shape = ContentShape::Powerline(
+    let separator = ContentItem::powerline(
         PowerlineStyle::Octagon,
         PowerlineFill::Full,
         PowerlineDirection::Left,
@@ -358,40 +217,21 @@ fn render(bar: &mut Bar) {
         1,
         Alignment::Right,
         &[
-            ContentItem {
-                bg: black,
-                fg: white,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: white,
-                fg: red,
-                shape: ContentShape::Text("          ".to_owned()),
-            },
-            ContentItem {
-                bg: white,
-                fg: red,
-                shape: shape.clone(),
-            },
-            ContentItem {
-                bg: red,
-                fg: white,
-                shape: ContentShape::Text("".to_owned()),
-            },
-            ContentItem {
-                bg: red,
-                fg: black,
-                shape: shape.clone(),
-            },
+            separator.clone().fg(white).bg(black),
+            ContentItem::text("          ").fg(red).bg(white),
+            separator.clone().fg(red).bg(white),
+            ContentItem::text("").fg(white).bg(red),
+            separator.clone().fg(black).bg(red),
         ],
     );
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let args: Vec<String> = env::args().collect();
     let custom_text = (args.len() > 1).then(|| &args[1]);
 
-    let mut bar = Bar::new();
+    let mut bar = Bar::new().await;
     bar.clear_monitors();
 
     if let Some(text) = custom_text {
@@ -402,5 +242,5 @@ fn main() {
 
     bar.present();
     bar.flush();
-    std::thread::sleep(std::time::Duration::from_secs(10));
+    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
 }
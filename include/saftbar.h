@@ -0,0 +1,72 @@
+/* C API of saftbar, available when building with `--features ffi`.
+ *
+ * The API is immediate mode: collect the content of a frame with
+ * `saftbar_draw_text` and put it on screen with `saftbar_present`. Events are
+ * polled without blocking, wait for `saftbar_fd` to become readable in between.
+ *
+ * Except for `saftbar_free`, `bar` must not be NULL.
+ */
+#ifndef SAFTBAR_H
+#define SAFTBAR_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Saftbar saftbar_t;
+
+#define SAFTBAR_ALIGN_LEFT 0
+#define SAFTBAR_ALIGN_CENTER 1
+#define SAFTBAR_ALIGN_RIGHT 2
+
+#define SAFTBAR_EVENT_HOVER 0
+#define SAFTBAR_EVENT_CLICK 1
+#define SAFTBAR_EVENT_RECONNECTED 2
+#define SAFTBAR_EVENT_OTHER 3
+/* The window manager asked to close the bar, or the process received SIGTERM. */
+#define SAFTBAR_EVENT_CLOSE 4
+
+/* Fields that don't apply to `kind` are -1 or NULL. */
+typedef struct {
+    int kind;
+    int monitor;
+    int alignment;
+    int index;
+    int button;
+    /* The `on_click` action of the clicked item. Valid until the next call on the bar. */
+    const char *action;
+} saftbar_event_t;
+
+/* Connect to the X server and create a bar on each monitor. Returns NULL on failure. */
+saftbar_t *saftbar_new(void);
+/* Hide the bars, remove their struts and free all resources. `bar` may be NULL. */
+void saftbar_free(saftbar_t *bar);
+
+/* `bar` must not be NULL. */
+int saftbar_monitor_count(const saftbar_t *bar);
+/* The file descriptor of the X connection. It changes after SAFTBAR_EVENT_RECONNECTED.
+ * `bar` must not be NULL. */
+int saftbar_fd(const saftbar_t *bar);
+
+/* Append a text item to the next frame. Colors are 0xAARRGGBB, `on_click` may be NULL.
+ * Returns 0 on success, -1 on invalid arguments. `bar` and `text` must not be NULL. */
+int saftbar_draw_text(saftbar_t *bar, int monitor, int alignment, const char *text, uint32_t fg,
+                      uint32_t bg, const char *on_click);
+
+/* Replace the content of all monitors with the items drawn since the last call and show it.
+ * `bar` must not be NULL. */
+void saftbar_present(saftbar_t *bar);
+
+/* Returns 1 and fills `event` if there was an event, 0 otherwise. Once the connection to the
+ * X server is lost, this blocks until the bar has reconnected, then reports
+ * SAFTBAR_EVENT_RECONNECTED; `saftbar_fd` must be queried again afterwards.
+ * `bar` and `event` must not be NULL. */
+int saftbar_poll_event(saftbar_t *bar, saftbar_event_t *event);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
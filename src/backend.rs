@@ -0,0 +1,152 @@
+use std::fmt::Debug;
+use std::future::Future;
+
+use crate::xft::RGBA;
+
+#[cfg(feature = "headless")]
+pub mod headless;
+pub mod x11;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rectangle {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// A side of a monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceKind {
+    /// A bar docked to an edge of a monitor, reserving space for itself.
+    Dock(Edge),
+    /// A short-lived window on top of everything else, dismissed by clicking elsewhere.
+    Popup,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Weight {
+    #[default]
+    Regular,
+    Bold,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Slant {
+    #[default]
+    Roman,
+    Italic,
+}
+
+/// Variant of the font to draw text with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TextStyle {
+    pub weight: Weight,
+    pub slant: Slant,
+    /// Draw all of the text with the font for icons, if the backend has a separate one.
+    pub icon: bool,
+}
+
+/// An RGBA pixel buffer, row by row.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub w: u32,
+    pub h: u32,
+    pub pixels: Vec<RGBA>,
+}
+
+impl Image {
+    pub(crate) fn new(w: u32, h: u32) -> Self {
+        Self {
+            w,
+            h,
+            pixels: vec![(0, 0, 0, 0); w as usize * h as usize],
+        }
+    }
+
+    /// Encode the image as PNG into `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if writing fails.
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, writer: impl std::io::Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.w, self.h);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data = self
+            .pixels
+            .iter()
+            .flat_map(|&(r, g, b, a)| [r, g, b, a])
+            .collect::<Vec<_>>();
+        encoder.write_header()?.write_image_data(&data)
+    }
+}
+
+/// Everything `Bar` needs to put pixels on the screen.
+///
+/// The layout logic in `Bar` only talks to this trait, so that it works the same for X11 and
+/// any other target that can fill shapes and draw text.
+pub trait RenderBackend {
+    /// Something that can be drawn to and presented, e.g. a window with its backing pixmap.
+    type Surface: Copy + PartialEq + Debug;
+
+    /// The height of a line of text in the regular style. This is the minimum height of the bar.
+    fn line_height(&self) -> u32;
+
+    /// The distance from the top of a line of text to its baseline.
+    fn baseline(&self) -> u32;
+
+    /// The height of lowercase letters without ascenders, e.g. `x`, above the baseline.
+    fn x_height(&self) -> u32;
+
+    /// The horizontal advance of `text` in `style`, i.e. where the next text would start.
+    fn text_width(&self, text: &str, style: TextStyle) -> u32;
+
+    /// Create a surface covering `rect` in screen coordinates.
+    fn create_surface(&mut self, rect: &Rectangle, kind: SurfaceKind) -> Self::Surface;
+
+    /// Show or hide a surface. Hidden docks don't reserve space on the screen.
+    fn set_surface_visible(&mut self, surface: Self::Surface, visible: bool);
+
+    /// Make a surface translucent, from `0.0` to fully opaque at `1.0`. Backends that can't blend
+    /// with what's behind the surface may ignore this.
+    fn set_surface_opacity(&mut self, surface: Self::Surface, opacity: f32);
+
+    fn destroy_surface(&mut self, surface: Self::Surface);
+
+    /// Fill `rects`, given in surface coordinates, with `color`.
+    fn fill_rects(&mut self, surface: Self::Surface, color: RGBA, rects: &[Rectangle]);
+
+    /// Fill convex polygons, given as lists of points in surface coordinates, with `color`.
+    fn fill_polys(&mut self, surface: Self::Surface, color: RGBA, polys: &[Vec<(u32, u32)>]);
+
+    /// Draw `text` in `style` and `color` starting at `x`, with the top of its line at `y`. The
+    /// baseline is `baseline` below that.
+    fn draw_text(
+        &mut self,
+        surface: Self::Surface,
+        color: RGBA,
+        text: &str,
+        style: TextStyle,
+        x: u32,
+        y: u32,
+    );
+
+    /// The pixels drawn to `surface` so far, whether presented or not.
+    fn capture(&self, surface: Self::Surface) -> impl Future<Output = Image>;
+
+    /// Make everything drawn to `surfaces` so far visible.
+    fn present(&mut self, surfaces: &[Self::Surface]);
+
+    /// Send all pending requests.
+    fn flush(&self);
+}
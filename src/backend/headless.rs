@@ -0,0 +1,237 @@
+// Rasterizing converts between float and pixel coordinates all over the place.
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+
+use fontdue::{Font, FontSettings};
+
+pub use crate::backend::Image;
+use crate::backend::{Rectangle, RenderBackend, SurfaceKind, TextStyle};
+use crate::xft::RGBA;
+
+/// Index of an image of the `HeadlessBackend`, counting up in order of creation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeadlessSurface(pub usize);
+
+impl Image {
+    fn pixel_mut(&mut self, x: u32, y: u32) -> Option<&mut RGBA> {
+        (x < self.w && y < self.h).then(|| &mut self.pixels[(y * self.w + x) as usize])
+    }
+}
+
+/// Renders into memory instead of onto an X server.
+///
+/// Fills replace the pixels, like the X11 backend's Render fills with `PictOp::Src` do, alpha
+/// included. The X server stores those premultiplied by alpha, while images here hold the colors
+/// as given, which is also what the X11 backend's `capture` returns. Text is rasterized with
+/// `fontdue` and blended onto the background by glyph coverage. There is no X connection, so
+/// this is deterministic given the same font file, which makes it suitable for tests and for
+/// generating screenshots. There is only the one font file, so text styles are ignored.
+pub struct HeadlessBackend {
+    font: Font,
+    size: f32,
+    ascent: u32,
+    descent: u32,
+    images: Vec<Option<Image>>,
+}
+
+impl HeadlessBackend {
+    /// Load the font from the contents of a font file (e.g. a ttf) at pixel size `size`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `font_data` can't be parsed as a font.
+    pub fn new(font_data: &[u8], size: f32) -> Result<Self, &'static str> {
+        let font = Font::from_bytes(font_data, FontSettings::default())?;
+        let metrics = font
+            .horizontal_line_metrics(size)
+            .ok_or("Font has no horizontal metrics")?;
+
+        // Round outwards, like Xft does when reporting ascent and descent.
+        let ascent = metrics.ascent.ceil().max(0.0) as u32;
+        let descent = (-metrics.descent).ceil().max(0.0) as u32;
+
+        Ok(Self {
+            font,
+            size,
+            ascent,
+            descent,
+            images: Vec::new(),
+        })
+    }
+
+    /// The pixels of `surface`.
+    ///
+    /// # Panics
+    ///
+    /// If the surface has been destroyed already.
+    #[must_use]
+    pub fn image(&self, surface: HeadlessSurface) -> &Image {
+        self.images[surface.0]
+            .as_ref()
+            .expect("Surface has been destroyed")
+    }
+
+    fn image_mut(&mut self, surface: HeadlessSurface) -> &mut Image {
+        self.images[surface.0]
+            .as_mut()
+            .expect("Surface has been destroyed")
+    }
+}
+
+/// Blend `color` over `pixel` with the given coverage.
+fn blend(pixel: &mut RGBA, color: RGBA, coverage: u8) {
+    let c = u32::from(coverage);
+    let mix = |src: u8, dst: u8| ((u32::from(src) * c + u32::from(dst) * (255 - c)) / 255) as u8;
+    *pixel = (
+        mix(color.0, pixel.0),
+        mix(color.1, pixel.1),
+        mix(color.2, pixel.2),
+        mix(color.3, pixel.3),
+    );
+}
+
+/// Fill a polygon by sampling the pixel centers of every row.
+///
+/// Like with X, a pixel is drawn if its center lies inside the polygon, where left and top
+/// edges count as inside and right and bottom edges don't.
+fn fill_poly(image: &mut Image, color: RGBA, points: &[(u32, u32)]) {
+    let Some(y_min) = points.iter().map(|(_, y)| *y).min() else {
+        return;
+    };
+    let y_max = points.iter().map(|(_, y)| *y).max().unwrap_or(y_min);
+
+    let edges = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(&(x0, y0), &(x1, y1))| {
+            let (x0, y0, x1, y1) = (f64::from(x0), f64::from(y0), f64::from(x1), f64::from(y1));
+            ((x0, y0), (x1, y1))
+        })
+        .collect::<Vec<_>>();
+
+    for y in y_min..y_max {
+        let yc = f64::from(y) + 0.5;
+        let mut crossings = edges
+            .iter()
+            .filter(|((_, y0), (_, y1))| y0.min(*y1) <= yc && yc < y0.max(*y1))
+            .map(|((x0, y0), (x1, y1))| x0 + (yc - y0) * (x1 - x0) / (y1 - y0))
+            .collect::<Vec<_>>();
+        crossings.sort_by(f64::total_cmp);
+
+        for span in crossings.chunks_exact(2) {
+            let (xl, xr) = (span[0], span[1]);
+            // First and last pixel whose center is in [xl, xr).
+            let first = (xl - 0.5).ceil().max(0.0) as u32;
+            let end = (xr - 0.5).ceil().max(0.0) as u32;
+            for x in first..end {
+                if let Some(pixel) = image.pixel_mut(x, y) {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+}
+
+impl RenderBackend for HeadlessBackend {
+    type Surface = HeadlessSurface;
+
+    fn line_height(&self) -> u32 {
+        self.ascent + self.descent
+    }
+
+    fn baseline(&self) -> u32 {
+        self.ascent
+    }
+
+    fn x_height(&self) -> u32 {
+        self.font.metrics('x', self.size).height as u32
+    }
+
+    fn text_width(&self, text: &str, _style: TextStyle) -> u32 {
+        let width: f32 = text
+            .chars()
+            .map(|c| self.font.metrics(c, self.size).advance_width)
+            .sum();
+        width.round() as u32
+    }
+
+    fn create_surface(&mut self, rect: &Rectangle, _kind: SurfaceKind) -> HeadlessSurface {
+        self.images.push(Some(Image::new(rect.w, rect.h)));
+        HeadlessSurface(self.images.len() - 1)
+    }
+
+    fn set_surface_visible(&mut self, _surface: HeadlessSurface, _visible: bool) {}
+
+    fn set_surface_opacity(&mut self, _surface: HeadlessSurface, _opacity: f32) {}
+
+    fn destroy_surface(&mut self, surface: HeadlessSurface) {
+        self.images[surface.0] = None;
+    }
+
+    fn fill_rects(&mut self, surface: HeadlessSurface, color: RGBA, rects: &[Rectangle]) {
+        let image = self.image_mut(surface);
+        for rect in rects {
+            for y in rect.y..rect.y + rect.h {
+                for x in rect.x..rect.x + rect.w {
+                    if let Some(pixel) = image.pixel_mut(x, y) {
+                        *pixel = color;
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_polys(&mut self, surface: HeadlessSurface, color: RGBA, polys: &[Vec<(u32, u32)>]) {
+        let image = self.image_mut(surface);
+        for points in polys {
+            fill_poly(image, color, points);
+        }
+    }
+
+    fn draw_text(
+        &mut self,
+        surface: HeadlessSurface,
+        color: RGBA,
+        text: &str,
+        _style: TextStyle,
+        x: u32,
+        y: u32,
+    ) {
+        let baseline = i64::from(y + self.ascent);
+        let mut pen = f64::from(x);
+        let glyphs = text
+            .chars()
+            .map(|c| self.font.rasterize(c, self.size))
+            .collect::<Vec<_>>();
+
+        let image = self.image_mut(surface);
+        for (metrics, coverage) in glyphs {
+            let left = pen.round() as i64 + i64::from(metrics.xmin);
+            // `ymin` is the offset of the bottom edge of the bitmap relative to the baseline.
+            let top = baseline - i64::from(metrics.ymin) - metrics.height as i64;
+            for (row, line) in coverage.chunks(metrics.width.max(1)).enumerate() {
+                for (column, alpha) in line.iter().enumerate() {
+                    let px = u32::try_from(left + column as i64);
+                    let py = u32::try_from(top + row as i64);
+                    if let (Ok(px), Ok(py)) = (px, py) {
+                        if let Some(pixel) = image.pixel_mut(px, py) {
+                            blend(pixel, color, *alpha);
+                        }
+                    }
+                }
+            }
+            pen += f64::from(metrics.advance_width);
+        }
+    }
+
+    async fn capture(&self, surface: HeadlessSurface) -> Image {
+        self.image(surface).clone()
+    }
+
+    fn present(&mut self, _surfaces: &[HeadlessSurface]) {}
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,1018 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::fd::RawFd;
+use std::time::Duration;
+
+use log::{debug, error};
+use xcb::{present, render, sync, x, Xid};
+
+use crate::backend::{
+    Edge, Image, Rectangle, RenderBackend, Slant, SurfaceKind, TextStyle, Weight,
+};
+use crate::setup::{
+    ChangeProperty, CopyArea, FillPoly, FillRect, MapWindow, PresentPixmap, PropertyData,
+    RequestError, Setup, UnmapWindow,
+};
+use crate::xft::{Font, Xft, RGBA};
+
+/// A window and the pixmap that is drawn to and then copied onto the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct X11Surface {
+    pub(crate) window: x::Window,
+    pixmap: x::Pixmap,
+    kind: SurfaceKind,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+/// A surface presented through the Present extension. Its pixmap is copied to a second one that
+/// is handed to the server, so that drawing can go on while the server waits for the vertical
+/// blank.
+struct Presentation {
+    surface: X11Surface,
+    pixmap: x::Pixmap,
+    /// Presented, but not completed yet.
+    pending: bool,
+    /// Presented again while pending, to be presented once more when completed.
+    outdated: bool,
+}
+
+/// Where the bar windows are kept in the stack of windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stacking {
+    /// Leave it to the window manager, which usually keeps docks on top.
+    #[default]
+    Default,
+    /// Above all other windows, for window managers that let windows cover docks.
+    Above,
+    /// Below all other windows.
+    Below,
+}
+
+/// Atoms needed to make the window manager treat a window as a dock.
+struct DockAtoms {
+    desktop: x::Atom,
+    window_type: x::Atom,
+    window_type_dock: x::Atom,
+    state: x::Atom,
+    state_sticky: x::Atom,
+    state_above: x::Atom,
+    state_below: x::Atom,
+    strut_partial: x::Atom,
+    strut: x::Atom,
+    pid: x::Atom,
+    protocols: x::Atom,
+    delete_window: x::Atom,
+    opacity: x::Atom,
+}
+
+pub struct X11Backend {
+    pointer_grab: Option<x::Window>,
+    /// The popup to grab the pointer for, see `grab_pointer_for_popup`.
+    pending_pointer_grab: Option<x::Window>,
+    dock_atoms: DockAtoms,
+    /// Published as `WM_CLIENT_MACHINE`, together with `_NET_WM_PID`.
+    hostname: Option<String>,
+    /// The XSync counter of the time since the last user input, if the server has one.
+    idle_counter: Option<sync::Counter>,
+
+    // Note the reverse drop order! Children first.
+    #[cfg(feature = "cairo")]
+    cairo_surfaces: HashMap<x::Pixmap, crate::cairo::Surface>,
+    pictures: HashMap<x::Pixmap, render::Picture>,
+    presentations: HashMap<x::Window, Presentation>,
+    present_serial: u32,
+    color_gcs: HashMap<RGBA, x::Gcontext>,
+    /// Solid fill pictures, the sources for drawing text in a color.
+    fill_pictures: HashMap<RGBA, render::Picture>,
+    hand_cursor: x::Cursor,
+    /// Bold and italic variants of `font`, loaded on first use.
+    styled_fonts: RefCell<HashMap<TextStyle, Font>>,
+    /// The font at `icon_size`, for icons.
+    icon_font: Option<Font>,
+    font: Font,
+    font_family: String,
+    font_size: f32,
+    icon_size: Option<f32>,
+    xft: Xft,
+    setup: Setup,
+}
+
+impl X11Backend {
+    /// Connect to the X server and load the font.
+    ///
+    /// # Panics
+    ///
+    /// If the X server can't be reached.
+    pub async fn new() -> Self {
+        Self::try_new()
+            .await
+            .expect("Failed to connect to the X server")
+    }
+
+    /// Connect to the X server and load the font. Returns `None` if the X server can't be
+    /// reached.
+    pub async fn try_new() -> Option<Self> {
+        let setup = Setup::new().await?;
+        let xft = setup.create_xft();
+
+        // Use the `Propo` variant to get full size icons, while sacrificing monospace.
+        let font_family = "Ubuntu Mono Nerd Font Propo";
+        let font_size = 15.25;
+        let font = xft.create_font(font_family, font_size);
+        debug!("Loaded font: {font:#?}");
+
+        let [desktop, window_type, window_type_dock, state, state_sticky, state_above, state_below, strut_partial, strut, pid, protocols, delete_window, opacity] =
+            setup
+                .get_atoms(&[
+                    "_NET_WM_DESKTOP",
+                    "_NET_WM_WINDOW_TYPE",
+                    "_NET_WM_WINDOW_TYPE_DOCK",
+                    "_NET_WM_STATE",
+                    "_NET_WM_STATE_STICKY",
+                    "_NET_WM_STATE_ABOVE",
+                    "_NET_WM_STATE_BELOW",
+                    "_NET_WM_STRUT_PARTIAL",
+                    "_NET_WM_STRUT",
+                    "_NET_WM_PID",
+                    "WM_PROTOCOLS",
+                    "WM_DELETE_WINDOW",
+                    "_NET_WM_WINDOW_OPACITY",
+                ])
+                .await;
+        let dock_atoms = DockAtoms {
+            desktop,
+            window_type,
+            window_type_dock,
+            state,
+            state_sticky,
+            state_above,
+            state_below,
+            strut_partial,
+            strut,
+            pid,
+            protocols,
+            delete_window,
+            opacity,
+        };
+        let idle_counter = setup.query_idle_counter().await;
+
+        // `XC_hand2` from the standard cursor font.
+        let hand_cursor = setup.create_font_cursor(60);
+
+        Some(Self {
+            pointer_grab: None,
+            pending_pointer_grab: None,
+            dock_atoms,
+            hostname: read_hostname(),
+            idle_counter,
+            #[cfg(feature = "cairo")]
+            cairo_surfaces: HashMap::new(),
+            pictures: HashMap::new(),
+            presentations: HashMap::new(),
+            present_serial: 0,
+            color_gcs: HashMap::new(),
+            fill_pictures: HashMap::new(),
+            hand_cursor,
+            styled_fonts: RefCell::new(HashMap::new()),
+            icon_font: None,
+            font,
+            font_family: font_family.to_owned(),
+            font_size,
+            icon_size: None,
+            xft,
+            setup,
+        })
+    }
+
+    /// The regions of all connected monitors, ordered from left to right.
+    pub async fn query_monitor_regions(&self) -> Vec<Rectangle> {
+        self.setup.query_valid_crtc_regions().await
+    }
+
+    /// The names and regions of all connected outputs, including mirrored or contained ones.
+    pub async fn query_outputs(&self) -> Vec<(String, Rectangle)> {
+        self.setup.query_outputs().await
+    }
+
+    /// The region of the primary monitor, if the user has set one.
+    pub async fn query_primary_region(&self) -> Option<Rectangle> {
+        self.setup.query_primary_crtc_region().await
+    }
+
+    fn color_gc(&mut self, reference_drawable: x::Drawable, rgba: RGBA) -> x::Gcontext {
+        let setup = &self.setup;
+        *self.color_gcs.entry(rgba).or_insert_with(|| {
+            let color = premultiplied_pixel(rgba);
+            setup.create_gc(reference_drawable, &[x::Gc::Foreground(color)])
+        })
+    }
+
+    fn fill_picture(&mut self, rgba: RGBA) -> render::Picture {
+        let setup = &self.setup;
+        *self
+            .fill_pictures
+            .entry(rgba)
+            .or_insert_with(|| setup.create_solid_fill(rgba))
+    }
+
+    /// Free the glyphs of `font` on the server, unless they are gone with the connection.
+    fn close_font(&self, font: &Font) {
+        if self.is_connected() {
+            self.xft.close_font(&self.setup, font);
+        }
+    }
+
+    /// The family and size of the font.
+    #[must_use]
+    pub fn font(&self) -> (&str, f32) {
+        (&self.font_family, self.font_size)
+    }
+
+    /// Replace the font. Surfaces keep their size, the line height changes right away.
+    pub fn set_font(&mut self, font_family: &str, font_size: f32) {
+        let font = self.xft.create_font(font_family, font_size);
+        debug!("Loaded font: {font:#?}");
+        let old_font = std::mem::replace(&mut self.font, font);
+        self.close_font(&old_font);
+        for font in std::mem::take(self.styled_fonts.get_mut()).values() {
+            self.close_font(font);
+        }
+        self.font_family = font_family.to_owned();
+        self.font_size = font_size;
+        self.set_icon_size(self.icon_size);
+    }
+
+    /// The size of the font for icons, `None` if icons are drawn like text.
+    #[must_use]
+    pub fn icon_size(&self) -> Option<f32> {
+        self.icon_size
+    }
+
+    /// Draw icons with the font at `icon_size` instead of the size of the text. Icons are
+    /// centered on the line, the line height doesn't change.
+    pub fn set_icon_size(&mut self, icon_size: Option<f32>) {
+        if let Some(font) = self.icon_font.take() {
+            self.close_font(&font);
+        }
+        self.icon_font = icon_size.map(|size| {
+            let font = self.xft.create_font(&self.font_family, size);
+            debug!("Loaded icon font: {font:#?}");
+            font
+        });
+        self.icon_size = icon_size;
+    }
+
+    /// Call `f` with the variant of the font for `style`, loading it if necessary.
+    fn with_font<T>(&self, style: TextStyle, f: impl FnOnce(&Font) -> T) -> T {
+        // Icons are handled by `for_each_run`.
+        let style = TextStyle {
+            icon: false,
+            ..style
+        };
+        if style == TextStyle::default() {
+            return f(&self.font);
+        }
+
+        let mut styled_fonts = self.styled_fonts.borrow_mut();
+        let font = styled_fonts.entry(style).or_insert_with(|| {
+            let weight = match style.weight {
+                Weight::Regular => "regular",
+                Weight::Bold => "bold",
+            };
+            let slant = match style.slant {
+                Slant::Roman => "roman",
+                Slant::Italic => "italic",
+            };
+            // Fontconfig picks the closest match, e.g. synthesizes italics if there are none.
+            let pattern = format!("{}:weight={weight}:slant={slant}", self.font_family);
+            let font = self.xft.create_font(&pattern, self.font_size);
+            debug!("Loaded font for {style:?}: {font:#?}");
+            font
+        });
+        f(font)
+    }
+
+    /// Call `f` with each run of `text` and the font to draw it with: the icon font for icons,
+    /// if there is one, and the variant of the font for `style` otherwise. `f` is also told
+    /// whether it got the icon font.
+    fn for_each_run(&self, text: &str, style: TextStyle, mut f: impl FnMut(&str, &Font, bool)) {
+        let Some(icon_font) = &self.icon_font else {
+            return self.with_font(style, |font| f(text, font, false));
+        };
+        if style.icon {
+            return f(text, icon_font, true);
+        }
+        for (icons, run) in icon_runs(text) {
+            if icons {
+                f(run, icon_font, true);
+            } else {
+                self.with_font(style, |font| f(run, font, false));
+            }
+        }
+    }
+
+    /// Set EWMH or something values.
+    fn declare_dock(&self, window: x::Window, edge: Edge, rect: &Rectangle) {
+        use PropertyData::{Atom, Cardinal, SizeHints, String};
+
+        let atoms = &self.dock_atoms;
+        let window_type_dock = [atoms.window_type_dock];
+        let state_sticky = [atoms.state_sticky];
+        let protocols = [atoms.delete_window];
+        let name_bytes = "saftbar".as_bytes();
+
+        // Pin position and size for window managers that don't know about docks.
+        let us_position = 1;
+        let p_position = 1 << 2;
+        let p_min_size = 1 << 4;
+        let p_max_size = 1 << 5;
+        let p_win_gravity = 1 << 9;
+        let north_west_gravity = 1;
+        let Rectangle { x, y, w, h } = *rect;
+        let size_hints = [
+            us_position | p_position | p_min_size | p_max_size | p_win_gravity,
+            // Position and size, obsolete but still read by some window managers.
+            x,
+            y,
+            w,
+            h,
+            // Minimum and maximum size.
+            w,
+            h,
+            w,
+            h,
+            // Size increments, aspect ratios and base size are unused.
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            north_west_gravity,
+        ];
+
+        let properties = [
+            ChangeProperty(atoms.desktop, Cardinal(&[u32::MAX])),
+            ChangeProperty(atoms.window_type, Atom(&window_type_dock)),
+            ChangeProperty(atoms.state, Atom(&state_sticky)),
+            ChangeProperty(x::ATOM_WM_NAME, String(name_bytes)),
+            ChangeProperty(x::ATOM_WM_CLASS, String(name_bytes)),
+            ChangeProperty(x::ATOM_WM_NORMAL_HINTS, SizeHints(&size_hints)),
+            ChangeProperty(atoms.protocols, Atom(&protocols)),
+        ];
+        report_errors(self.setup.replace_properties(window, &properties));
+
+        // `_NET_WM_PID` is only meaningful together with the machine it refers to.
+        if let Some(hostname) = &self.hostname {
+            let properties = [
+                ChangeProperty(atoms.pid, Cardinal(&[std::process::id()])),
+                ChangeProperty(x::ATOM_WM_CLIENT_MACHINE, String(hostname.as_bytes())),
+            ];
+            report_errors(self.setup.replace_properties(window, &properties));
+        }
+        self.set_strut(window, Some((edge, rect)));
+    }
+
+    /// Reserve the space of `rect` at `edge` of the screen, or nothing at all.
+    fn set_strut(&self, window: x::Window, strut: Option<(Edge, &Rectangle)>) {
+        use PropertyData::Cardinal;
+
+        let (screen_w, screen_h) = self.setup.screen_size();
+        let strut_data = strut.map_or([0; 12], |(edge, rect)| {
+            strut_partial(edge, rect, screen_w, screen_h)
+        });
+
+        let atoms = &self.dock_atoms;
+        let properties = [
+            ChangeProperty(atoms.strut_partial, Cardinal(&strut_data)),
+            ChangeProperty(atoms.strut, Cardinal(&strut_data[..4])),
+        ];
+        report_errors(self.setup.replace_properties(window, &properties));
+    }
+
+    /// Poll for the next event. Fails if the connection to the X server has been lost.
+    pub fn poll_for_event(&self) -> Result<Option<xcb::Event>, xcb::ConnError> {
+        self.setup.poll_for_event()
+    }
+
+    /// Wait for the next event. Fails if the connection to the X server has been lost.
+    pub async fn wait_for_event(&self) -> Result<xcb::Event, xcb::ConnError> {
+        self.setup.wait_for_event().await
+    }
+
+    /// Whether the connection to the X server is still usable.
+    ///
+    /// Once it isn't, nothing is drawn or freed anymore: the server has freed everything along
+    /// with the connection.
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.setup.is_connected()
+    }
+
+    /// The number of X requests sent so far.
+    #[must_use]
+    pub fn requests_sent(&self) -> u64 {
+        self.setup.requests_sent()
+    }
+
+    #[must_use]
+    pub fn raw_connection_fd(&self) -> RawFd {
+        self.setup.raw_connection_fd()
+    }
+
+    /// Grab `keysym` with `modifiers` on all keycodes producing it.
+    /// Returns the keycodes for which the grab succeeded.
+    pub async fn grab_key(&self, modifiers: &[x::ModMask], keysym: x::Keysym) -> Vec<x::Keycode> {
+        let mut grabbed = Vec::new();
+        for keycode in self.setup.keysym_to_keycodes(keysym).await {
+            let mut result = Ok(());
+            for modifiers in modifiers {
+                result = self.setup.grab_key(keycode, *modifiers).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            match result {
+                Ok(()) => grabbed.push(keycode),
+                Err(err) => debug!("Failed to grab keysym {keysym} on keycode {keycode}; {err}"),
+            }
+        }
+        grabbed
+    }
+
+    /// Grab the pointer for the popup created last, so that clicks outside of it are reported as
+    /// well. Called by `Bar::next_event` before waiting, as grabbing takes a round trip.
+    pub(crate) async fn grab_pointer_for_popup(&mut self) {
+        let Some(window) = self.pending_pointer_grab.take() else {
+            return;
+        };
+        if !self.is_connected() {
+            return;
+        }
+        if self.setup.grab_pointer(window).await {
+            self.pointer_grab = Some(window);
+        } else {
+            debug!("Failed to grab the pointer, popup will not notice outside clicks");
+        }
+    }
+
+    /// Keep `surface` above or below other windows, both via `_NET_WM_STATE` for the window
+    /// manager and by restacking it directly.
+    pub fn set_stacking(&self, surface: X11Surface, stacking: Stacking) {
+        if !self.is_connected() {
+            return;
+        }
+
+        let atoms = &self.dock_atoms;
+        let window = surface.window;
+        let both = [atoms.state_above, atoms.state_below];
+        match stacking {
+            Stacking::Default => self.setup.change_wm_state(window, atoms.state, false, both),
+            Stacking::Above => {
+                let below = [atoms.state_below, x::Atom::none()];
+                let above = [atoms.state_above, x::Atom::none()];
+                self.setup
+                    .change_wm_state(window, atoms.state, false, below);
+                self.setup.change_wm_state(window, atoms.state, true, above);
+                self.setup.restack_window(window, x::StackMode::Above);
+            }
+            Stacking::Below => {
+                let above = [atoms.state_above, x::Atom::none()];
+                let below = [atoms.state_below, x::Atom::none()];
+                self.setup
+                    .change_wm_state(window, atoms.state, false, above);
+                self.setup.change_wm_state(window, atoms.state, true, below);
+                self.setup.restack_window(window, x::StackMode::Below);
+            }
+        }
+    }
+
+    /// Raise `surface` to the top of the stack.
+    pub fn raise(&self, surface: X11Surface) {
+        if self.is_connected() {
+            self.setup
+                .restack_window(surface.window, x::StackMode::Above);
+        }
+    }
+
+    /// Show a hand cursor over `surface`, or reset it to the default.
+    pub fn set_hand_cursor(&self, surface: X11Surface, hand: bool) {
+        if !self.is_connected() {
+            return;
+        }
+        let cursor = if hand {
+            self.hand_cursor
+        } else {
+            x::Cursor::none()
+        };
+        self.setup.set_window_cursor(surface.window, cursor);
+    }
+
+    /// The time since the last keyboard or pointer input anywhere on the display. `None` if the X
+    /// server doesn't provide the XSync `IDLETIME` counter.
+    pub async fn idle_time(&self) -> Option<Duration> {
+        let counter = self.idle_counter?;
+        let millis = self.setup.query_counter(counter).await?;
+        u64::try_from(millis).ok().map(Duration::from_millis)
+    }
+
+    /// Note the completion of a presentation, presenting the surface again if it has been
+    /// presented in the meantime. Returns whether the event has been consumed.
+    pub(crate) fn handle_present_event(&mut self, event: &xcb::Event) -> bool {
+        let xcb::Event::Present(present::Event::CompleteNotify(event)) = event else {
+            return false;
+        };
+        let Some(presentation) = self.presentations.get_mut(&event.window()) else {
+            return true;
+        };
+        presentation.pending = false;
+        if std::mem::take(&mut presentation.outdated) {
+            let surface = presentation.surface;
+            self.present(&[surface]);
+        }
+        true
+    }
+
+    /// Whether `event` is the window manager asking to close one of our windows.
+    pub(crate) fn is_delete_request(&self, event: &x::ClientMessageEvent) -> bool {
+        let atoms = &self.dock_atoms;
+        match event.data() {
+            x::ClientMessageData::Data32([protocol, ..]) => {
+                event.r#type() == atoms.protocols && protocol == atoms.delete_window.resource_id()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for X11Backend {
+    fn drop(&mut self) {
+        // The server has freed everything along with the connection.
+        if !self.is_connected() {
+            return;
+        }
+        for font in self
+            .styled_fonts
+            .get_mut()
+            .values()
+            .chain(&self.icon_font)
+            .chain([&self.font])
+        {
+            self.xft.close_font(&self.setup, font);
+        }
+        for picture in self.pictures.values().chain(self.fill_pictures.values()) {
+            self.setup.free_picture(*picture);
+        }
+        for gc in self.color_gcs.values() {
+            self.setup.free_gc(*gc);
+        }
+        self.setup.free_cursor(self.hand_cursor);
+        self.setup.flush();
+    }
+}
+
+/// The `_NET_WM_STRUT_PARTIAL` values reserving `rect` at `edge` of a screen of the given size.
+///
+/// Struts are measured from the edges of the screen, not of the monitor, and the start and end
+/// coordinates are inclusive.
+fn strut_partial(edge: Edge, rect: &Rectangle, screen_w: u32, screen_h: u32) -> [u32; 12] {
+    let Rectangle { x, y, w, h } = *rect;
+    let (x_end, y_end) = ((x + w).saturating_sub(1), (y + h).saturating_sub(1));
+
+    // Widths of the left, right, top and bottom struts, followed by the start and end
+    // coordinates of each of them in the same order.
+    let mut strut = [0; 12];
+    let (side, width, range) = match edge {
+        Edge::Left => (0, x + w, [y, y_end]),
+        Edge::Right => (1, screen_w.saturating_sub(x), [y, y_end]),
+        Edge::Top => (2, y + h, [x, x_end]),
+        Edge::Bottom => (3, screen_h.saturating_sub(y), [x, x_end]),
+    };
+    strut[side] = width;
+    strut[4 + 2 * side..6 + 2 * side].copy_from_slice(&range);
+    strut
+}
+
+/// `rgba` as a pixel of the 32bit visual, premultiplied by alpha like the fills of
+/// `Setup::fill_rects`.
+fn premultiplied_pixel((r, g, b, a): RGBA) -> u32 {
+    let a = u32::from(a);
+    let channel = |value: u8| u32::from(value) * a / 255;
+    channel(b) | channel(g) << 8 | channel(r) << 16 | a << 24
+}
+
+/// The color of a `pixel` of the 32bit visual, which is premultiplied by alpha. The inverse of
+/// `premultiplied_pixel`, up to rounding.
+fn unpremultiplied(pixel: u32) -> RGBA {
+    let [a, r, g, b] = pixel.to_be_bytes();
+    if a == 0 {
+        return (r, g, b, a);
+    }
+    let channel = |value: u8| {
+        let value = u32::from(value) * 255 / u32::from(a);
+        u8::try_from(value).unwrap_or(u8::MAX)
+    };
+    (channel(r), channel(g), channel(b), a)
+}
+
+/// Whether `c` is in one of the private use areas of Unicode, where Nerd Fonts put their icons.
+fn is_icon(c: char) -> bool {
+    matches!(
+        c,
+        '\u{E000}'..='\u{F8FF}' | '\u{F0000}'..='\u{FFFFD}' | '\u{100000}'..='\u{10FFFD}'
+    )
+}
+
+/// Split `text` into runs of icons and runs of other characters, telling which is which.
+fn icon_runs(text: &str) -> Vec<(bool, &str)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut icons = false;
+    for (index, c) in text.char_indices() {
+        if is_icon(c) != icons {
+            if index > start {
+                runs.push((icons, &text[start..index]));
+            }
+            start = index;
+            icons = !icons;
+        }
+    }
+    if start < text.len() {
+        runs.push((icons, &text[start..]));
+    }
+    runs
+}
+
+/// The name of this machine, if the kernel tells us.
+fn read_hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+/// Log failed requests and carry on. A request on a window that vanished with a monitor, for
+/// example, is no reason to bring down the whole bar.
+fn report_errors(result: Result<(), Vec<RequestError>>) {
+    for err in result.err().unwrap_or_default() {
+        error!("{err}");
+    }
+}
+
+impl RenderBackend for X11Backend {
+    type Surface = X11Surface;
+
+    fn line_height(&self) -> u32 {
+        self.font.asc_and_desc()
+    }
+
+    fn baseline(&self) -> u32 {
+        self.font.ascent()
+    }
+
+    fn x_height(&self) -> u32 {
+        self.xft.x_height(&self.font)
+    }
+
+    fn text_width(&self, text: &str, style: TextStyle) -> u32 {
+        let mut width = 0;
+        self.for_each_run(text, style, |run, font, _| {
+            width += self.xft.cursor_offset(run, font);
+        });
+        width
+    }
+
+    fn create_surface(&mut self, rect: &Rectangle, kind: SurfaceKind) -> X11Surface {
+        let Rectangle { x, y, w, h } = *rect;
+        if !self.is_connected() {
+            // Drawing to this surface is skipped as well, until the bar reconnects.
+            return X11Surface {
+                window: x::Window::none(),
+                pixmap: x::Pixmap::none(),
+                kind,
+                x,
+                y,
+                w,
+                h,
+            };
+        }
+
+        let override_redirect = kind == SurfaceKind::Popup;
+        let (window, pixmap) =
+            self.setup
+                .create_window_and_pixmap(x, y, w, h, self.setup.colormap, override_redirect);
+
+        if let SurfaceKind::Dock(edge) = kind {
+            self.declare_dock(window, edge, rect);
+        }
+
+        // Make the window visible.
+        report_errors(self.setup.map_windows(&[MapWindow(window)]));
+
+        if kind == SurfaceKind::Popup {
+            self.pending_pointer_grab = Some(window);
+        }
+
+        let picture = self.setup.create_picture(x::Drawable::Pixmap(pixmap));
+        self.pictures.insert(pixmap, picture);
+        #[cfg(feature = "cairo")]
+        self.cairo_surfaces
+            .insert(pixmap, self.setup.create_cairo_surface(pixmap, w, h));
+
+        let surface = X11Surface {
+            window,
+            pixmap,
+            kind,
+            x,
+            y,
+            w,
+            h,
+        };
+        if self.setup.has_present() {
+            self.setup.select_present_events(window);
+            let presentation = Presentation {
+                surface,
+                pixmap: self.setup.create_pixmap(window, w, h),
+                pending: false,
+                outdated: false,
+            };
+            self.presentations.insert(window, presentation);
+        }
+        surface
+    }
+
+    fn set_surface_visible(&mut self, surface: X11Surface, visible: bool) {
+        if !self.is_connected() {
+            return;
+        }
+
+        let X11Surface {
+            window,
+            kind,
+            x,
+            y,
+            w,
+            h,
+            ..
+        } = surface;
+        // Only docks reserve space.
+        let edge = match kind {
+            SurfaceKind::Dock(edge) => Some(edge),
+            SurfaceKind::Popup => None,
+        };
+        if visible {
+            if let Some(edge) = edge {
+                self.set_strut(window, Some((edge, &Rectangle { x, y, w, h })));
+            }
+            report_errors(self.setup.map_windows(&[MapWindow(window)]));
+        } else {
+            report_errors(self.setup.unmap_windows(&[UnmapWindow(window)]));
+            if edge.is_some() {
+                self.set_strut(window, None);
+            }
+        }
+    }
+
+    fn set_surface_opacity(&mut self, surface: X11Surface, opacity: f32) {
+        use PropertyData::Cardinal;
+
+        if !self.is_connected() {
+            return;
+        }
+        // Applied by the compositor, without one this has no effect.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let opacity = (f64::from(opacity.clamp(0.0, 1.0)) * f64::from(u32::MAX)) as u32;
+        let properties = [ChangeProperty(
+            self.dock_atoms.opacity,
+            Cardinal(&[opacity]),
+        )];
+        report_errors(self.setup.replace_properties(surface.window, &properties));
+    }
+
+    fn destroy_surface(&mut self, surface: X11Surface) {
+        if !self.is_connected() {
+            // The server has freed everything along with the connection, cairo needn't try.
+            #[cfg(feature = "cairo")]
+            std::mem::forget(self.cairo_surfaces.remove(&surface.pixmap));
+            self.pictures.remove(&surface.pixmap);
+            self.presentations.remove(&surface.window);
+            self.pointer_grab = None;
+            self.pending_pointer_grab = None;
+            return;
+        }
+        if self.pending_pointer_grab == Some(surface.window) {
+            self.pending_pointer_grab = None;
+        }
+        if self.pointer_grab == Some(surface.window) {
+            self.setup.ungrab_pointer();
+            self.pointer_grab = None;
+        }
+        #[cfg(feature = "cairo")]
+        self.cairo_surfaces.remove(&surface.pixmap);
+        if let Some(picture) = self.pictures.remove(&surface.pixmap) {
+            self.setup.free_picture(picture);
+        }
+        if let Some(presentation) = self.presentations.remove(&surface.window) {
+            self.setup.free_pixmap(presentation.pixmap);
+        }
+        self.setup
+            .destroy_window_and_pixmap(surface.window, surface.pixmap);
+    }
+
+    fn fill_rects(&mut self, surface: X11Surface, color: RGBA, rects: &[Rectangle]) {
+        if !self.is_connected() {
+            return;
+        }
+        let picture = self.pictures[&surface.pixmap];
+        let rects = rects
+            .iter()
+            .map(|&Rectangle { x, y, w, h }| FillRect(picture, color, x, y, w, h))
+            .collect::<Vec<_>>();
+        report_errors(self.setup.fill_rects(&rects));
+    }
+
+    fn fill_polys(&mut self, surface: X11Surface, color: RGBA, polys: &[Vec<(u32, u32)>]) {
+        if !self.is_connected() {
+            return;
+        }
+        #[cfg(feature = "cairo")]
+        if let Some(cairo_surface) = self.cairo_surfaces.get(&surface.pixmap) {
+            cairo_surface.fill_polys(color, polys);
+            return;
+        }
+
+        let drawable = x::Drawable::Pixmap(surface.pixmap);
+        let gc = self.color_gc(drawable, color);
+        let polys = polys
+            .iter()
+            .map(|points| FillPoly(drawable, gc, points.clone()))
+            .collect::<Vec<_>>();
+        report_errors(self.setup.fill_polys(&polys));
+    }
+
+    fn draw_text(
+        &mut self,
+        surface: X11Surface,
+        color: RGBA,
+        text: &str,
+        style: TextStyle,
+        x: u32,
+        y: u32,
+    ) {
+        if !self.is_connected() {
+            return;
+        }
+        let picture = self.pictures[&surface.pixmap];
+        let fill = self.fill_picture(color);
+        let height = self.line_height();
+        let mut x = x;
+        self.for_each_run(text, style, |run, font, icons| {
+            let (top, run_height) = if icons {
+                let icon_height = font.asc_and_desc();
+                let offset = (i64::from(height) - i64::from(icon_height)) / 2;
+                let top = y.saturating_add_signed(i32::try_from(offset).unwrap_or(0));
+                (top, icon_height)
+            } else {
+                (y, height)
+            };
+            report_errors(self.xft.draw_string(
+                &self.setup,
+                run,
+                picture,
+                fill,
+                font,
+                top,
+                run_height,
+                x,
+            ));
+            x += self.xft.cursor_offset(run, font);
+        });
+    }
+
+    async fn capture(&self, surface: X11Surface) -> Image {
+        let mut image = Image::new(surface.w, surface.h);
+        if !self.is_connected() {
+            return image;
+        }
+        let drawable = x::Drawable::Pixmap(surface.pixmap);
+        if let Some(pixels) = self.setup.get_image(drawable, surface.w, surface.h).await {
+            image.pixels = pixels.into_iter().map(unpremultiplied).collect();
+        }
+        image
+    }
+
+    fn present(&mut self, surfaces: &[X11Surface]) {
+        if !self.is_connected() {
+            return;
+        }
+        let Some(reference) = surfaces.first() else {
+            return;
+        };
+        // Any graphics context with the right depth will do for copying.
+        let gc = self.color_gc(x::Drawable::Pixmap(reference.pixmap), (0, 0, 0, 255));
+
+        let mut areas = Vec::with_capacity(surfaces.len());
+        let mut presents = Vec::new();
+        for surface in surfaces {
+            let X11Surface {
+                window,
+                pixmap,
+                w,
+                h,
+                ..
+            } = *surface;
+            match self.presentations.get_mut(&window) {
+                // Presenting at most once per frame throttles to the refresh rate.
+                Some(presentation) if presentation.pending => presentation.outdated = true,
+                Some(presentation) => {
+                    let target = x::Drawable::Pixmap(presentation.pixmap);
+                    areas.push(CopyArea(pixmap, target, gc, w, h));
+                    self.present_serial = self.present_serial.wrapping_add(1);
+                    presents.push(PresentPixmap(
+                        window,
+                        presentation.pixmap,
+                        self.present_serial,
+                    ));
+                    presentation.pending = true;
+                }
+                None => areas.push(CopyArea(pixmap, x::Drawable::Window(window), gc, w, h)),
+            }
+        }
+        report_errors(self.setup.copy_areas(&areas));
+        report_errors(self.setup.present_pixmaps(&presents));
+    }
+
+    fn flush(&self) {
+        if self.is_connected() {
+            self.setup.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1920x1080 monitor at (0, 360) left of a 2560x1440 monitor at (1920, 0).
+    const SCREEN: (u32, u32) = (4480, 1440);
+
+    fn strut(edge: Edge, x: u32, y: u32, w: u32, h: u32) -> [u32; 12] {
+        strut_partial(edge, &Rectangle { x, y, w, h }, SCREEN.0, SCREEN.1)
+    }
+
+    #[test]
+    fn struts_for_each_edge() {
+        assert_eq!(
+            strut(Edge::Top, 1920, 0, 2560, 20),
+            [0, 0, 20, 0, 0, 0, 0, 0, 1920, 4479, 0, 0]
+        );
+        // The top of the lower monitor is 360 pixels below the top of the screen.
+        assert_eq!(
+            strut(Edge::Top, 0, 360, 1920, 20),
+            [0, 0, 380, 0, 0, 0, 0, 0, 0, 1919, 0, 0]
+        );
+        assert_eq!(
+            strut(Edge::Bottom, 0, 1420, 1920, 20),
+            [0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 1919]
+        );
+        assert_eq!(
+            strut(Edge::Bottom, 1920, 1400, 2560, 20),
+            [0, 0, 0, 40, 0, 0, 0, 0, 0, 0, 1920, 4479]
+        );
+        assert_eq!(
+            strut(Edge::Left, 1920, 100, 30, 1340),
+            [1950, 0, 0, 0, 100, 1439, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            strut(Edge::Right, 4450, 0, 30, 1080),
+            [0, 30, 0, 0, 0, 0, 0, 1079, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            strut(Edge::Right, 1890, 360, 30, 1080),
+            [0, 2590, 0, 0, 0, 0, 360, 1439, 0, 0, 0, 0]
+        );
+    }
+    #[test]
+    fn premultiplied_pixels() {
+        let opaque = (200, 100, 50, 255);
+        assert_eq!(premultiplied_pixel(opaque), 0xffc8_6432);
+        assert_eq!(unpremultiplied(premultiplied_pixel(opaque)), opaque);
+
+        // A translucent fill reads back as its color, not darkened by its alpha.
+        let translucent = (255, 0, 0, 128);
+        assert_eq!(premultiplied_pixel(translucent), 0x8080_0000);
+        assert_eq!(unpremultiplied(0x8080_0000), translucent);
+        let (r, g, b, a) = unpremultiplied(premultiplied_pixel((200, 100, 50, 128)));
+        assert_eq!(a, 128);
+        for (channel, expected) in [(r, 200), (g, 100), (b, 50)] {
+            assert!(channel.abs_diff(expected) <= 2, "{channel} != {expected}");
+        }
+
+        assert_eq!(unpremultiplied(0), (0, 0, 0, 0));
+    }
+}
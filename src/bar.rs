@@ -1,497 +1,3060 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
-use log::debug;
-use tokio::io::unix::AsyncFd;
-use xcb::{x, Xid};
+use log::{debug, warn};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+use xcb::x;
 
-use crate::setup::{ChangeProperty, CopyArea, FillPoly, FillRect, PropertyData, Rectangle, Setup};
-use crate::xft::{Draw, Font, Xft, RGBA};
+use crate::backend::x11::{Stacking, X11Backend};
+use crate::backend::{
+    Edge, Image, Rectangle, RenderBackend, Slant, SurfaceKind, TextStyle, Weight,
+};
+use crate::xft::RGBA;
 
-struct Monitor {
+/// Color of the bar where no item is drawn.
+const CLEAR_COLOR: RGBA = (0, 0, 0, 255);
+
+/// An item as it has been laid out on a monitor, kept for pointer hit-testing.
+struct Region {
+    alignment: Alignment,
+    index: usize,
     x: u32,
-    // y: u32,
+    y: u32,
     w: u32,
+    item: ContentItem,
+    /// Whether the alternate content of the item is shown, see `ContentItem::alternate`.
+    alternate: bool,
+}
 
-    // Note the reverse drop order! Children first.
-    pixmap: x::Pixmap,
-    window: xcb::x::Window,
+/// A region as far as its pixels are concerned, see `Bar::set_content`.
+#[derive(PartialEq)]
+struct PaintedRegion {
+    x: u32,
+    w: u32,
+    item: ContentItem,
+    alternate: bool,
+    hovered: bool,
+}
+
+impl Region {
+    fn location(&self, monitor_index: usize) -> ItemLocation {
+        ItemLocation {
+            monitor: monitor_index,
+            alignment: self.alignment,
+            index: self.index,
+        }
+    }
+
+    fn shape(&self) -> &ContentShape {
+        match &self.item.alternate {
+            Some(alternate) if self.alternate => &alternate.shape,
+            _ => &self.item.shape,
+        }
+    }
+}
+
+struct Monitor<S> {
+    /// Names of the outputs showing this monitor, more than one if they are mirrored.
+    names: Vec<String>,
+    x: u32,
+    y: u32,
+    w: u32,
+    enabled: bool,
+    regions: Vec<Region>,
+    /// The right block, if it doesn't fit next to the other blocks.
+    pages: Option<Pages>,
+    surface: S,
 }
 
-#[derive(Copy, Clone)]
+/// The items of a right block that is shown one page at a time.
+struct Pages {
+    items: Vec<ContentItem>,
+    page: usize,
+    count: usize,
+}
+
+impl Pages {
+    fn turn(&mut self, forward: bool) {
+        self.page = if forward {
+            (self.page + 1) % self.count
+        } else {
+            (self.page + self.count - 1) % self.count
+        };
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Alignment {
     Left,
     Center,
     Right,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PowerlineStyle {
     Powerline,
     Octagon,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PowerlineFill {
     Full,
     No,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PowerlineDirection {
     Left,
     Right,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ContentShape {
     Text(String),
     Powerline(PowerlineStyle, PowerlineFill, PowerlineDirection),
+    /// A row of dots, one per page, with the one of the current `page` highlighted. Added to
+    /// paginated right blocks, see `Bar::draw`.
+    PageDots {
+        page: usize,
+        count: usize,
+    },
+}
+
+impl Default for ContentShape {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+/// A fixed position within an alignment block or a popup row, see `ContentItem::tab_stop`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TabStop {
+    Pixels(u32),
+    /// Multiples of the width of `0`, which is the width of every character in monospace fonts.
+    Cells(u32),
+}
+
+/// An outline drawn inside the background of an item, see `ContentItem::border`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Border {
+    pub color: RGBA,
+    /// The thickness in pixels.
+    pub width: u32,
+}
+
+/// Content shown instead of that of an item after clicking it, see `ContentItem::alternate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alternate {
+    /// Identifies the toggle across calls to `Bar::draw`, e.g. `clock`.
+    pub name: String,
+    pub shape: ContentShape,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct ContentItem {
     pub fg: RGBA,
     pub bg: RGBA,
     pub shape: ContentShape,
+    /// Foreground color used instead of `fg` while the pointer is over this item.
+    pub hover_fg: Option<RGBA>,
+    /// Background color used instead of `bg` while the pointer is over this item.
+    pub hover_bg: Option<RGBA>,
+    /// Action reported by `Event::Click` when this item is clicked.
+    pub on_click: Option<String>,
+    /// Start this item at the given offset from the start of its block, so that columns stay
+    /// in place when the items before change their width. The previous item is widened to fill
+    /// the gap. Ignored if the previous items extend past the tab stop already.
+    pub tab_stop: Option<TabStop>,
+    /// Weight and slant select a variant of the font for text, e.g. bold for emphasis.
+    pub weight: Weight,
+    pub slant: Slant,
+    /// Move the text or shape this many pixels down, or up if negative, without moving the
+    /// background. Corrects icons that sit off-center in their font.
+    pub y_offset: i32,
+    /// Extra pixels between the characters of text, e.g. for labels in capitals.
+    pub letter_spacing: u32,
+    /// Cross out text, e.g. to show that a connection is down.
+    pub strikethrough: bool,
+    /// Underline text, e.g. to mark the focused workspace.
+    pub underline: bool,
+    /// Draw the text like icons, see `Bar::set_icon_size`. Characters in the private use areas
+    /// of Unicode, where Nerd Fonts put their icons, are drawn like icons anyway.
+    pub icon: bool,
+    /// Extend the background by this many pixels before and after the text or shape, e.g. for
+    /// colored chips. The padding counts towards the width of the item.
+    pub padding_left: u32,
+    pub padding_right: u32,
+    /// Outline the background, e.g. for chips. Give the item some padding to keep the border
+    /// clear of the text.
+    pub border: Option<Border>,
+    /// Items of the same group within a block can be collapsed into the first one of them with
+    /// `Bar::set_group_collapsed`, e.g. behind an icon. Clicking the first item collapses or
+    /// expands the group, unless it has an `on_click` action.
+    pub group: Option<String>,
+    /// Content to show instead of `shape` after clicking the item, e.g. the date instead of the
+    /// time. Clicking again switches back, unless the item has an `on_click` action. Which
+    /// toggles are switched is kept by name, see `Bar::set_alternate_shown`.
+    pub alternate: Option<Alternate>,
 }
 
-pub struct Bar {
-    height: u32,
+impl ContentItem {
+    fn text_style(&self) -> TextStyle {
+        TextStyle {
+            weight: self.weight,
+            slant: self.slant,
+            icon: self.icon,
+        }
+    }
 
-    // Note the reverse drop order! Children first.
-    color_gcs: HashMap<RGBA, x::Gcontext>,
-    clear_gc: x::Gcontext,
-    font: Font,
-    xft: Xft,
-    monitors: Vec<Monitor>,
-    setup: Setup,
-}
-
-impl Bar {
-    #[must_use]
-    pub fn new() -> Self {
-        let setup = Setup::new();
-        let valid_regions = setup.query_valid_crtc_regions();
-        let mut xft = setup.create_xft();
-
-        // Use the `Propo` variant to get full size icons, while sacrificing monospace.
-        let font_family = "Ubuntu Mono Nerd Font Propo";
-        let font = xft.create_font(font_family, 15.25);
-        debug!("Loaded font: {font:#?}");
-
-        debug!("Creating windows");
-        let height = font.asc_and_desc();
-        let monitors = valid_regions
-            .into_iter()
-            .map(|Rectangle { x, y, w, .. }| {
-                let (window, pixmap) =
-                    setup.create_window_and_pixmap(x, y, w, height, setup.colormap);
-
-                Monitor {
-                    x,
-                    // y,
-                    w,
-                    pixmap,
-                    window,
-                }
-            })
-            .collect::<Vec<_>>();
+    fn has_hover_style(&self) -> bool {
+        self.hover_fg.is_some() || self.hover_bg.is_some()
+    }
 
-        // Set EWMH or something values.
-        debug!("Setting EWMH or something atoms");
-        {
-            use PropertyData::{Atom, Cardinal, String};
-
-            // Create atoms.
-            let [desktop, window_type, window_type_dock, state, state_sticky, strut_partial, strut] =
-                setup.get_atoms(&[
-                    "_NET_WM_DESKTOP",
-                    "_NET_WM_WINDOW_TYPE",
-                    "_NET_WM_WINDOW_TYPE_DOCK",
-                    "_NET_WM_STATE",
-                    "_NET_WM_STATE_STICKY",
-                    "_NET_WM_STRUT_PARTIAL",
-                    "_NET_WM_STRUT",
-                ]);
-
-            let window_type_dock = [window_type_dock];
-            let state_sticky = [state_sticky];
-            let name_bytes = "saftbar".as_bytes();
-            let properties = [
-                ChangeProperty(desktop, Cardinal(&[u32::MAX])),
-                ChangeProperty(window_type, Atom(&window_type_dock)),
-                ChangeProperty(state, Atom(&state_sticky)),
-                ChangeProperty(x::ATOM_WM_NAME, String(name_bytes)),
-                ChangeProperty(x::ATOM_WM_CLASS, String(name_bytes)),
-            ];
-
-            // Set window properties.
-            for monitor in &monitors {
-                setup.replace_properties(monitor.window, &properties);
-
-                let h = height;
-                let sx = monitor.x;
-                let ex = sx + monitor.w;
-                let strut_data = [0, 0, h, 0, 0, 0, 0, 0, sx, ex, 0, 0];
-                let monitor_properties = [
-                    ChangeProperty(strut_partial, Cardinal(&strut_data)),
-                    ChangeProperty(strut, Cardinal(&strut_data[..4])),
-                ];
-                setup.replace_properties(monitor.window, &monitor_properties);
-            }
-        }
-
-        // This is needed to get the root/depth. The root window has 24bpp, we want 32. Why?
-        let reference_drawable = x::Drawable::Window(monitors[0].window);
-        let clear_gc = setup.create_gc(reference_drawable, &[x::Gc::Foreground(0xFF00_0000)]);
-
-        // Make windows visible.
-        debug!("Mapping windows");
-        setup.map_windows(
-            &monitors
-                .iter()
-                .map(|monitor| crate::setup::MapWindow(monitor.window))
-                .collect::<Vec<_>>(),
-        );
+    fn colors(&self, hovered: bool) -> (RGBA, RGBA) {
+        if hovered {
+            (
+                self.hover_fg.unwrap_or(self.fg),
+                self.hover_bg.unwrap_or(self.bg),
+            )
+        } else {
+            (self.fg, self.bg)
+        }
+    }
 
-        setup.flush();
-        debug!("Bar initialization done");
+    /// An item showing `text` in white on the color of an empty bar, to be refined with the
+    /// methods below, e.g. `ContentItem::text("cpu").fg(red).pad(4)`.
+    #[must_use]
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            fg: (255, 255, 255, 255),
+            bg: CLEAR_COLOR,
+            shape: ContentShape::Text(text.into()),
+            ..Default::default()
+        }
+    }
 
-        // TODO handle signals.
-        // TODO Use execution path: arg0.
-        // TODO clickable areas.
+    /// A separator in white on the color of an empty bar, see `ContentItem::text`.
+    #[must_use]
+    pub fn powerline(
+        style: PowerlineStyle,
+        fill: PowerlineFill,
+        direction: PowerlineDirection,
+    ) -> Self {
+        Self {
+            shape: ContentShape::Powerline(style, fill, direction),
+            ..Self::text("")
+        }
+    }
+
+    #[must_use]
+    pub fn fg(self, fg: RGBA) -> Self {
+        Self { fg, ..self }
+    }
+
+    #[must_use]
+    pub fn bg(self, bg: RGBA) -> Self {
+        Self { bg, ..self }
+    }
 
+    #[must_use]
+    pub fn hover_fg(self, hover_fg: RGBA) -> Self {
         Self {
-            height,
-            setup,
-            xft,
-            font,
-            monitors,
-            clear_gc,
-            color_gcs: HashMap::new(),
+            hover_fg: Some(hover_fg),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn hover_bg(self, hover_bg: RGBA) -> Self {
+        Self {
+            hover_bg: Some(hover_bg),
+            ..self
         }
     }
 
-    fn cache_color(&mut self, reference_drawable: x::Drawable, rgba: RGBA) {
-        if self.color_gcs.get(&rgba).is_none() {
-            let r = u32::from(rgba.0);
-            let g = u32::from(rgba.1);
-            let b = u32::from(rgba.2);
-            let a = u32::from(rgba.3);
-            let color = b | g << 8 | r << 16 | a << 24;
+    #[must_use]
+    pub fn on_click(self, action: impl Into<String>) -> Self {
+        Self {
+            on_click: Some(action.into()),
+            ..self
+        }
+    }
 
-            let gc = self
-                .setup
-                .create_gc(reference_drawable, &[x::Gc::Foreground(color)]);
+    #[must_use]
+    pub fn tab_stop(self, tab_stop: TabStop) -> Self {
+        Self {
+            tab_stop: Some(tab_stop),
+            ..self
+        }
+    }
 
-            self.color_gcs.insert(rgba, gc);
+    #[must_use]
+    pub fn bold(self) -> Self {
+        Self {
+            weight: Weight::Bold,
+            ..self
         }
     }
 
-    fn get_color(&self, rgba: RGBA) -> x::Gcontext {
-        self.color_gcs
-            .get(&rgba)
-            .copied()
-            .expect("Color is not cached")
+    #[must_use]
+    pub fn italic(self) -> Self {
+        Self {
+            slant: Slant::Italic,
+            ..self
+        }
     }
 
-    pub fn clear_monitors(&self) {
-        self.setup.fill_rects(
-            &self
-                .monitors
-                .iter()
-                .map(|monitor| {
-                    FillRect(
-                        x::Drawable::Pixmap(monitor.pixmap),
-                        self.clear_gc,
-                        0,
-                        0,
-                        monitor.w,
-                        self.height,
-                    )
-                })
-                .collect::<Vec<_>>(),
-        );
+    #[must_use]
+    pub fn y_offset(self, y_offset: i32) -> Self {
+        Self { y_offset, ..self }
     }
 
-    fn cache_colors(&mut self, monitor_index: usize, texts: &[ContentItem]) {
-        let pixmap = self.monitors[monitor_index].pixmap;
-        let drawable = x::Drawable::Pixmap(pixmap);
-        for text in texts {
-            self.cache_color(drawable, text.bg);
+    #[must_use]
+    pub fn letter_spacing(self, letter_spacing: u32) -> Self {
+        Self {
+            letter_spacing,
+            ..self
         }
     }
 
-    fn render_handles(&self, monitor_index: usize) -> (x::Drawable, Draw, u32) {
-        let monitor = &self.monitors[monitor_index];
-        let pixmap = monitor.pixmap;
-        (
-            x::Drawable::Pixmap(pixmap),
-            self.xft.new_draw(u64::from(pixmap.resource_id())),
-            monitor.w,
-        )
+    #[must_use]
+    pub fn strikethrough(self) -> Self {
+        Self {
+            strikethrough: true,
+            ..self
+        }
     }
 
-    fn cursor_offset(&self, item: &ContentItem) -> u32 {
-        match &item.shape {
-            ContentShape::Text(text) => self.xft.cursor_offset(text, &self.font),
-            ContentShape::Powerline(_, _, _) => (self.height + 1) / 2,
-            // ContentShape::Powerline(PowerlineStyle::Octagon, _, _) => self.height / 4 + 1,
+    #[must_use]
+    pub fn underline(self) -> Self {
+        Self {
+            underline: true,
+            ..self
         }
     }
 
-    fn shape_powerline(
-        &self,
-        xl: u32,
-        direction: PowerlineDirection,
-        fill: PowerlineFill,
-    ) -> Vec<Vec<(u32, u32)>> {
-        let h = self.height;
-        let h_2 = h / 2;
+    #[must_use]
+    pub fn icon(self) -> Self {
+        Self { icon: true, ..self }
+    }
 
-        let w = (h + 1) / 2;
-        let xr = xl + w;
+    /// Pad both sides by `padding` pixels.
+    #[must_use]
+    pub fn pad(self, padding: u32) -> Self {
+        self.padding(padding, padding)
+    }
 
-        let yt = 0;
-        let yb = h;
+    #[must_use]
+    pub fn padding(self, padding_left: u32, padding_right: u32) -> Self {
+        Self {
+            padding_left,
+            padding_right,
+            ..self
+        }
+    }
 
-        match (direction, fill) {
-            (PowerlineDirection::Left, PowerlineFill::Full) => {
-                vec![vec![
-                    (xl, yt + h_2),
-                    (xl, yb - h_2 - 1),
-                    (xr, yb),
-                    (xr, yt),
-                    (xr - 1, yt),
-                ]]
-            }
-            (PowerlineDirection::Right, PowerlineFill::Full) => {
-                vec![vec![
-                    (xl, yb),
-                    (xr, yb - h_2 - 1),
-                    (xr, yt + h_2),
-                    (xl + 1, yt),
-                    (xl, yt),
-                ]]
-            }
-            (PowerlineDirection::Left, PowerlineFill::No) => {
-                vec![
-                    vec![(xl, yt + h_2), (xl, yt + h_2 + 1), (xr, yt), (xr - 1, yt)],
-                    vec![
-                        (xl, yb - h_2 - 1),
-                        (xr, yb),
-                        (xr, yb - 1),
-                        (xl + 1, yb - h_2 - 1),
-                    ],
-                ]
-            }
-            (PowerlineDirection::Right, PowerlineFill::No) => {
-                vec![
-                    vec![(xl, yt), (xr, yt + h_2 + 1), (xr, yt + h_2), (xl + 1, yt)],
-                    vec![
-                        (xl, yb),
-                        (xr, yb - h_2 - 1),
-                        (xr - 1, yb - h_2 - 1),
-                        (xl, yb - 1),
-                    ],
-                ]
-            }
+    #[must_use]
+    pub fn border(self, color: RGBA, width: u32) -> Self {
+        Self {
+            border: Some(Border { color, width }),
+            ..self
         }
     }
 
-    fn shape_octagon(
-        &self,
-        xl: u32,
-        direction: PowerlineDirection,
-        fill: PowerlineFill,
-    ) -> Vec<Vec<(u32, u32)>> {
-        // Consult a pixel editor for this.
-        // We want to use truncating division for odd numbers and get one less than the
-        // half for even numbers. Exactly half would point to the first line in the
-        // second half of the row.
+    #[must_use]
+    pub fn group(self, group: impl Into<String>) -> Self {
+        Self {
+            group: Some(group.into()),
+            ..self
+        }
+    }
 
-        let h = self.height;
-        let h_4 = h / 4;
+    #[must_use]
+    pub fn alternate(self, name: impl Into<String>, shape: ContentShape) -> Self {
+        Self {
+            alternate: Some(Alternate {
+                name: name.into(),
+                shape,
+            }),
+            ..self
+        }
+    }
+}
 
-        let yt = 0;
-        let yb = h;
+impl From<&str> for ContentItem {
+    fn from(text: &str) -> Self {
+        Self::text(text)
+    }
+}
 
-        match direction {
-            PowerlineDirection::Right => {
-                let xr = xl + h_4 + 1;
+impl From<String> for ContentItem {
+    fn from(text: String) -> Self {
+        Self::text(text)
+    }
+}
 
-                match fill {
-                    PowerlineFill::Full => {
-                        vec![vec![
-                            (xl, yb),
-                            (xr, yb - h_4 - 1),
-                            (xr, yt + h_4),
-                            (xl + 1, yt),
-                            (xl, yt),
-                        ]]
-                    }
-                    PowerlineFill::No => {
-                        vec![
-                            vec![(xl, yt), (xr, yt + h_4 + 1), (xr, yt + h_4), (xl + 1, yt)],
-                            vec![
-                                (xr - 1, yt + h_4),
-                                (xr - 1, yb - h_4),
-                                (xr, yb - h_4),
-                                (xr, yt + h_4),
-                            ],
-                            vec![
-                                (xl, yb),
-                                (xr, yb - h_4 - 1),
-                                (xr - 1, yb - h_4 - 1),
-                                (xl, yb - 1),
-                            ],
-                        ]
-                    }
-                }
-            }
-            PowerlineDirection::Left => {
-                let w = (self.height + 1) / 2;
-                let xr = xl + w;
-                let xl = xr - h_4 - 1;
+/// Identifies an item previously passed to `Bar::draw`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemLocation {
+    pub monitor: usize,
+    pub alignment: Alignment,
+    /// Index into the items slice that was drawn.
+    pub index: usize,
+}
 
-                match fill {
-                    PowerlineFill::Full => {
-                        vec![vec![
-                            (xl, yt + h_4),
-                            (xl, yb - h_4 - 1),
-                            (xr, yb),
-                            (xr, yt),
-                            (xr - 1, yt),
-                        ]]
-                    }
-                    PowerlineFill::No => {
-                        vec![
-                            vec![(xl, yt + h_4), (xl, yt + h_4 + 1), (xr, yt), (xr - 1, yt)],
-                            vec![
-                                (xl, yt + h_4),
-                                (xl, yb - h_4),
-                                (xl + 1, yb - h_4),
-                                (xl + 1, yt + h_4),
-                            ],
-                            vec![
-                                (xl, yb - h_4 - 1),
-                                (xr, yb),
-                                (xr, yb - 1),
-                                (xl + 1, yb - h_4 - 1),
-                            ],
-                        ]
-                    }
-                }
-            }
+#[derive(Clone, Copy, Debug)]
+pub enum PopupPlacement {
+    Below,
+    Above,
+}
+
+/// A small window showing rows of items next to a bar item.
+struct Popup<S> {
+    anchor: ItemLocation,
+    /// In screen coordinates.
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    regions: Vec<Region>,
+    /// Close the popup on clicks on items without action as well, see `Bar::confirm`.
+    close_on_click: bool,
+    surface: S,
+}
+
+/// A key combination grabbed with `Bar::grab_key`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: x::ModMask,
+    pub keysym: x::Keysym,
+}
+
+/// Modifiers that should not prevent hotkeys from triggering: caps lock and num lock.
+const IGNORED_MODIFIERS: [x::ModMask; 4] = [
+    x::ModMask::empty(),
+    x::ModMask::LOCK,
+    x::ModMask::N2,
+    x::ModMask::LOCK.union(x::ModMask::N2),
+];
+
+#[derive(Debug)]
+pub enum Event {
+    /// The pointer moved onto a different item, or off of all items (`None`).
+    Hover(Option<ItemLocation>),
+    /// An item with an `on_click` action has been clicked. For items in a popup, `location` is
+    /// the item that the popup is anchored to, and the popup has been closed.
+    Click {
+        location: ItemLocation,
+        button: x::Button,
+        action: String,
+    },
+    /// A key combination registered with `Bar::grab_key` has been pressed.
+    Hotkey(Hotkey),
+    /// The connection to the X server was lost and has been reestablished. The bars have been
+    /// recreated on the monitors that exist now, with the previous content where possible.
+    Reconnected,
+    /// The window manager asked to close a bar (`WM_DELETE_WINDOW`), e.g. because the user
+    /// killed it, or the process received `SIGTERM`. Call `Bar::shutdown` to exit cleanly, or
+    /// ignore it to keep the bar.
+    Close,
+    /// The update interval of `Bar::run` has passed. Not emitted by `Bar::next_event`.
+    Tick,
+    /// Any other X event.
+    X(xcb::Event),
+}
+
+/// What ended a wait for X input, see `Bar::next_event_before`.
+enum Wakeup {
+    X(Result<xcb::Event, xcb::ConnError>),
+    Deadline,
+    Terminate,
+}
+
+/// Where the extra pixel goes when text can't be centered vertically, because the bar is an odd
+/// number of pixels higher than the font.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OddOverhang {
+    /// Leave the extra pixel below the text.
+    #[default]
+    Below,
+    /// Leave the extra pixel above the text.
+    Above,
+    /// Make the bar one pixel higher, so that the text is centered exactly.
+    Grow,
+}
+
+/// Options for creating a bar, see `Bar::with_options`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BarOptions {
+    /// The height of the bar in pixels. Bars are never lower than a line of text, which is also
+    /// the default, unless `Geometry::h` sets an exact height.
+    pub min_height: u32,
+    pub odd_overhang: OddOverhang,
+    /// Dim the bars once there has been no keyboard or pointer input for this long, e.g. to
+    /// spare OLED panels. They fade out over a fraction of a second and light up again at once
+    /// when the pointer moves over them or content is presented. Needs the XSync extension and a
+    /// compositor.
+    pub dim_after: Option<Duration>,
+    /// Move the content of the bars sideways by up to 2 pixels every so often, to spare OLED
+    /// panels. The bars themselves and the space they reserve stay in place. The content moves
+    /// with the next call to `Bar::present` once the interval has passed.
+    pub pixel_shift_interval: Option<Duration>,
+    /// Turn the pages of right blocks that don't fit, see `Bar::draw`, with the next call to
+    /// `Bar::present` once the interval has passed. Pages can be turned by scrolling anyway.
+    pub page_interval: Option<Duration>,
+    /// The edge of their monitors that the bars are docked to.
+    pub position: Position,
+    /// Where the bars are placed on their monitors, by default across their full width.
+    pub geometry: Geometry,
+    /// Run the `on_click` actions of clicked items as shell commands instead of reporting them
+    /// as `Event::Click`. The commands find the index of the monitor in `SAFTBAR_MONITOR` and
+    /// the mouse button in `SAFTBAR_BUTTON`.
+    pub spawn_actions: bool,
+}
+
+/// The edge of a monitor that a bar is docked to, see `BarOptions::position`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Position {
+    #[default]
+    Top,
+    Bottom,
+}
+
+impl Position {
+    fn edge(self) -> Edge {
+        match self {
+            Self::Top => Edge::Top,
+            Self::Bottom => Edge::Bottom,
         }
     }
+}
 
-    fn shape_polys(
-        &self,
-        xl: u32,
-        style: PowerlineStyle,
-        direction: PowerlineDirection,
-        fill: PowerlineFill,
-    ) -> Vec<Vec<(u32, u32)>> {
-        match style {
-            PowerlineStyle::Powerline => self.shape_powerline(xl, direction, fill),
-            PowerlineStyle::Octagon => self.shape_octagon(xl, direction, fill),
+/// Where a bar is placed on its monitor, like lemonbar's `-g WxH+X+Y`, e.g. to cover only part
+/// of a monitor. The space reserved for the bar reaches from the edge of the screen that the bar
+/// is docked to up to the bar, and only spans its width.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Geometry {
+    /// The offset from the left edge of the monitor.
+    pub x: u32,
+    /// The offset from the edge of the monitor that the bar is docked to, see
+    /// `BarOptions::position`.
+    pub y: u32,
+    /// The width of the bar, by default the rest of the monitor. Bars never reach past the
+    /// right edge of their monitor.
+    pub w: Option<u32>,
+    /// The exact height of the bar, instead of `BarOptions::min_height`. Text higher than that
+    /// is cut off at the bottom. An odd pixel of `OddOverhang::Grow` goes above the text
+    /// instead, as the bar can't grow.
+    pub h: Option<u32>,
+}
+
+impl Geometry {
+    /// The part of the monitor at `region` that a bar of `height` docked to `position` covers.
+    fn place(self, position: Position, height: u32, region: &Rectangle) -> Rectangle {
+        let x = self.x.min(region.w);
+        let y = match position {
+            Position::Top => self.y.min(region.h.saturating_sub(height)),
+            Position::Bottom => region.h.saturating_sub(self.y.saturating_add(height)),
+        };
+        let available = region.w - x;
+        Rectangle {
+            x: region.x + x,
+            y: region.y + y,
+            w: self.w.map_or(available, |w| w.min(available)),
+            h: height,
         }
     }
+}
 
-    pub fn draw(&mut self, monitor_index: usize, alignment: Alignment, items: &[ContentItem]) {
-        self.cache_colors(monitor_index, items);
+/// The opacities that dimming bars fade through, ending at that of dimmed bars.
+const DIM_STEPS: [f32; 4] = [0.85, 0.7, 0.55, 0.4];
 
-        let item_widths = items
-            .iter()
-            .map(|item| self.cursor_offset(item))
-            .collect::<Vec<_>>();
+/// The time between the steps of fading out.
+const DIM_STEP_INTERVAL: Duration = Duration::from_millis(60);
 
-        let (draw, text_draw, monitor_width) = self.render_handles(monitor_index);
+/// The horizontal offsets of the content cycled through by `BarOptions::pixel_shift_interval`.
+/// Content is kept vertically in place, as bars are often exactly as high as the text.
+const PIXEL_SHIFTS: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
 
-        // Where i start rendering depends on the alignment and the width of the content.
-        let mut cursor_offset = match alignment {
-            Alignment::Left => 0,
-            Alignment::Center => (monitor_width - item_widths.iter().sum::<u32>()) / 2,
-            Alignment::Right => monitor_width - item_widths.iter().sum::<u32>(),
+/// The height of a line of items and the offset of the text within it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct LineGeometry {
+    height: u32,
+    text_top: u32,
+}
+
+impl LineGeometry {
+    fn new(text_height: u32, options: &BarOptions) -> Self {
+        let exact_height = options.geometry.h;
+        let height = exact_height.unwrap_or(options.min_height.max(text_height));
+        let overhang = height.saturating_sub(text_height);
+        if overhang.is_multiple_of(2) {
+            return Self {
+                height,
+                text_top: overhang / 2,
+            };
+        }
+
+        let (height, text_top) = match options.odd_overhang {
+            OddOverhang::Below => (height, overhang / 2),
+            OddOverhang::Above => (height, overhang / 2 + 1),
+            OddOverhang::Grow if exact_height.is_some() => (height, overhang / 2 + 1),
+            OddOverhang::Grow => (height + 1, overhang / 2 + 1),
         };
+        Self { height, text_top }
+    }
+}
 
-        for (ContentItem { fg, bg, shape }, width) in items.iter().zip(item_widths.into_iter()) {
-            // Background color.
-            let color_gc = self.get_color(*bg);
-            let rect = FillRect(draw, color_gc, cursor_offset, 0, width, self.height);
-            self.setup.fill_rects(&[rect]);
-
-            match shape {
-                ContentShape::Text(text) => {
-                    // Foreground text.
-                    let fg = self.xft.create_color(*fg);
-                    self.xft.draw_string(
-                        text,
-                        &text_draw,
-                        &fg,
-                        &self.font,
-                        self.height,
-                        cursor_offset,
-                    );
-                }
-                ContentShape::Powerline(style, fill, direction) => {
-                    let color_gc = self.get_color(*fg);
-                    let polys = self
-                        .shape_polys(cursor_offset, *style, *direction, *fill)
-                        .into_iter()
-                        .map(|points| FillPoly(draw, color_gc, points))
-                        .collect::<Vec<_>>();
-                    self.setup.fill_polys(&polys);
-                }
-            }
+/// A monitor with a bar, see `Bar::monitors`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// The index to pass to e.g. `Bar::draw`.
+    pub index: usize,
+    /// The names of the outputs showing the monitor, see `Bar::monitor_names`.
+    pub names: Vec<String>,
+    /// The area covered by the bar, see `Bar::monitor_geometry`.
+    pub geometry: Rectangle,
+    pub primary: bool,
+    pub enabled: bool,
+}
+
+/// Counters for monitoring a long-running bar.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Calls to `Bar::present`.
+    pub frames: u64,
+    /// X requests sent, across reconnects. Only counted by `Bar::stats` of the X11 backend.
+    pub requests: u64,
+    /// X events taken from the connection by `Bar::next_event`.
+    pub events: u64,
+}
+
+pub struct Bar<B: RenderBackend = X11Backend> {
+    options: BarOptions,
+    line: LineGeometry,
+    hovered: Option<ItemLocation>,
+    popup: Option<Popup<B::Surface>>,
+    hotkeys: Vec<(x::Keycode, Hotkey)>,
+    primary: Option<usize>,
+    stacking: Stacking,
+    collapsed_groups: Vec<String>,
+    /// Names of the toggles showing their alternate content.
+    alternates_shown: Vec<String>,
+    stats: Stats,
+    /// How many of `DIM_STEPS` the bars have faded through, 0 if they are lit.
+    dim_step: usize,
+    /// Index into `PIXEL_SHIFTS` and when it was last advanced.
+    pixel_shift: usize,
+    shifted_at: Instant,
+    /// When the pages of the right blocks were last turned by `BarOptions::page_interval`.
+    paged_at: Instant,
+    /// `SIGTERM`, reported as `Event::Close`. Only registered for bars on an X server.
+    terminate: Option<Signal>,
+
+    // Note the reverse drop order! Children first.
+    monitors: Vec<Monitor<B::Surface>>,
+    backend: B,
+}
+
+impl Bar<X11Backend> {
+    /// Connect to the X server and create a bar on top of each monitor.
+    pub async fn new() -> Self {
+        Self::with_options(BarOptions::default()).await
+    }
+
+    /// Connect to the X server and create a bar on top of each monitor, configured by `options`.
+    ///
+    /// # Panics
+    ///
+    /// If the X server can't be reached.
+    pub async fn with_options(options: BarOptions) -> Self {
+        Self::try_with_options(options)
+            .await
+            .expect("Failed to connect to the X server")
+    }
+
+    /// Like `with_options`, but returns `None` if the X server can't be reached.
+    pub async fn try_with_options(options: BarOptions) -> Option<Self> {
+        let backend = X11Backend::try_new().await?;
+        let valid_regions = backend.query_monitor_regions().await;
+        let primary_region = backend.query_primary_region().await;
+        let outputs = backend.query_outputs().await;
+        let mut bar = Self::with_backend_options(backend, &valid_regions, options);
+        bar.primary = primary_index(&valid_regions, primary_region.as_ref());
+        assign_output_names(&mut bar.monitors, &valid_regions, &outputs);
+        debug!("Bar initialization done");
+
+        bar.terminate = signal(SignalKind::terminate())
+            .map_err(|err| warn!("Failed to handle SIGTERM; {err}"))
+            .ok();
+        // TODO Use execution path: arg0.
+
+        Some(bar)
+    }
+}
+
+impl<B: RenderBackend> Bar<B> {
+    /// Create a bar on top of each of `regions`, drawing with `backend`.
+    pub fn with_backend(backend: B, regions: &[Rectangle]) -> Self {
+        Self::with_backend_options(backend, regions, BarOptions::default())
+    }
+
+    /// Create a bar on top of each of `regions`, drawing with `backend`, configured by `options`.
+    pub fn with_backend_options(
+        mut backend: B,
+        regions: &[Rectangle],
+        options: BarOptions,
+    ) -> Self {
+        let line = LineGeometry::new(backend.line_height(), &options);
+        let monitors = create_monitors(&mut backend, line.height, &options, regions);
 
-            cursor_offset += width;
+        Self {
+            options,
+            line,
+            hovered: None,
+            popup: None,
+            hotkeys: Vec::new(),
+            primary: None,
+            stacking: Stacking::Default,
+            collapsed_groups: Vec::new(),
+            alternates_shown: Vec::new(),
+            stats: Stats::default(),
+            dim_step: 0,
+            pixel_shift: 0,
+            shifted_at: Instant::now(),
+            paged_at: Instant::now(),
+            terminate: None,
+            monitors,
+            backend,
         }
     }
 
-    pub fn present(&self) {
-        self.setup.copy_areas(
-            &self
-                .monitors
-                .iter()
-                .map(|monitor| {
-                    CopyArea(
-                        monitor.pixmap,
-                        monitor.window,
-                        self.clear_gc,
-                        monitor.w,
-                        self.height,
-                    )
-                })
-                .collect::<Vec<_>>(),
-        );
+    #[must_use]
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
-    pub fn flush(&self) {
-        self.setup.flush();
+    /// The height of the bars in pixels.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.line.height
     }
 
-    pub async fn next_x_event(&self) -> xcb::Event {
-        loop {
-            if let Some(event) = self.setup.poll_for_event() {
-                return event;
-            }
+    /// The distance from the top of the bar to the baseline of text in the regular style.
+    #[must_use]
+    pub fn baseline(&self) -> u32 {
+        self.line.text_top + self.backend.baseline()
+    }
 
-            let async_fd = AsyncFd::new(self.setup.raw_connection_fd())
-                .expect("Failed to initialize async fd");
-            // Drop the guard immediately. We are only interested in noticing action on the
-            // file descriptor.
-            let _ = async_fd
-                .readable()
-                .await
-                .expect("Failed to wait for events");
+    /// The area covered by the bar on the monitor at `monitor_index`, in screen coordinates.
+    #[must_use]
+    pub fn monitor_geometry(&self, monitor_index: usize) -> Rectangle {
+        let monitor = &self.monitors[monitor_index];
+        Rectangle {
+            x: monitor.x,
+            y: monitor.y,
+            w: monitor.w,
+            h: self.line.height,
         }
     }
-}
 
-impl Default for Bar {
-    fn default() -> Self {
-        Self::new()
+    /// The surface that the monitor at `monitor_index` is drawn to.
+    #[must_use]
+    pub fn monitor_surface(&self, monitor_index: usize) -> B::Surface {
+        self.monitors[monitor_index].surface
+    }
+
+    #[must_use]
+    pub fn monitor_count(&self) -> usize {
+        self.monitors.len()
+    }
+
+    /// The names of the outputs showing the monitor at `monitor_index`, e.g. `HDMI-1`. More than
+    /// one if they are mirrored, none for backends without outputs.
+    ///
+    /// Monitors are indexed from left to right, and monitors with the same left edge from top to
+    /// bottom, so indices stay the same as long as the arrangement of the outputs does.
+    #[must_use]
+    pub fn monitor_names(&self, monitor_index: usize) -> &[String] {
+        &self.monitors[monitor_index].names
+    }
+
+    /// The index of the monitor shown on the output called `name`, e.g. `HDMI-1`.
+    #[must_use]
+    pub fn monitor_index(&self, name: &str) -> Option<usize> {
+        self.monitors
+            .iter()
+            .position(|monitor| monitor.names.iter().any(|n| n == name))
+    }
+
+    #[must_use]
+    pub fn is_monitor_enabled(&self, monitor_index: usize) -> bool {
+        self.monitors[monitor_index].enabled
+    }
+
+    /// Show or hide the bar on a monitor, e.g. on a beamer while presenting. A hidden bar
+    /// doesn't reserve space on the screen. Its content is kept and can still be drawn to.
+    pub fn set_monitor_enabled(&mut self, monitor_index: usize, enabled: bool) {
+        let monitor = &mut self.monitors[monitor_index];
+        if monitor.enabled == enabled {
+            return;
+        }
+        monitor.enabled = enabled;
+        let surface = monitor.surface;
+
+        if !enabled {
+            if self
+                .hovered
+                .is_some_and(|location| location.monitor == monitor_index)
+            {
+                self.hovered = None;
+            }
+            if self
+                .popup_anchor()
+                .is_some_and(|anchor| anchor.monitor == monitor_index)
+            {
+                self.close_popup();
+            }
+        }
+
+        self.backend.set_surface_visible(surface, enabled);
+        if enabled {
+            self.backend.present(&[surface]);
+        }
+        self.flush();
+    }
+
+    /// The index of the primary monitor, if the user has set one.
+    ///
+    /// Widgets like the tray or the clock are conventionally only shown on the primary monitor.
+    #[must_use]
+    pub fn primary_monitor(&self) -> Option<usize> {
+        self.primary
+    }
+
+    /// All monitors in the order of their indices, to decide what to show where, e.g. the tray
+    /// only on the widest monitor.
+    #[must_use]
+    pub fn monitors(&self) -> Vec<MonitorInfo> {
+        (0..self.monitors.len())
+            .map(|index| MonitorInfo {
+                index,
+                names: self.monitor_names(index).to_vec(),
+                geometry: self.monitor_geometry(index),
+                primary: self.primary == Some(index),
+                enabled: self.is_monitor_enabled(index),
+            })
+            .collect()
+    }
+
+    pub fn clear_monitors(&mut self) {
+        for monitor in &mut self.monitors {
+            monitor.regions.clear();
+        }
+        self.clear_surfaces();
+    }
+
+    fn clear_surfaces(&mut self) {
+        for monitor in &self.monitors {
+            let rect = Rectangle {
+                x: 0,
+                y: 0,
+                w: monitor.w,
+                h: self.line.height,
+            };
+            self.backend
+                .fill_rects(monitor.surface, CLEAR_COLOR, &[rect]);
+        }
+    }
+
+    /// Replace the surfaces of all monitors with ones of the current height, and lay out and
+    /// draw their content again. Stacking is not restored.
+    fn recreate_surfaces(&mut self) {
+        self.close_popup();
+        self.hovered = None;
+        let old_height = self.line.height;
+        self.line = LineGeometry::new(self.backend.line_height(), &self.options);
+
+        let position = self.options.position;
+        for index in 0..self.monitors.len() {
+            let monitor = &mut self.monitors[index];
+            // Bottom bars keep their lower edge in place.
+            if position == Position::Bottom {
+                monitor.y = (monitor.y + old_height).saturating_sub(self.line.height);
+            }
+            let rect = Rectangle {
+                x: monitor.x,
+                y: monitor.y,
+                w: monitor.w,
+                h: self.line.height,
+            };
+            self.backend.destroy_surface(monitor.surface);
+            let surface = self
+                .backend
+                .create_surface(&rect, SurfaceKind::Dock(position.edge()));
+            self.monitors[index].surface = surface;
+            if !self.monitors[index].enabled {
+                self.backend.set_surface_visible(surface, false);
+            }
+        }
+
+        // Widths depend on the font, so the items need a new layout.
+        self.relayout_monitors();
+    }
+
+    /// Lay out and draw the content of all monitors again.
+    fn relayout_monitors(&mut self) {
+        self.clear_surfaces();
+        for index in 0..self.monitors.len() {
+            let blocks = [Alignment::Left, Alignment::Center, Alignment::Right]
+                .map(|alignment| (alignment, self.block_items(index, alignment)));
+            self.monitors[index].regions.clear();
+            for (alignment, items) in blocks {
+                if !items.is_empty() {
+                    self.draw(index, alignment, &items);
+                }
+            }
+        }
+    }
+
+    /// The items last drawn as the block of `alignment` on the monitor at `monitor_index`,
+    /// including those on other pages.
+    fn block_items(&self, monitor_index: usize, alignment: Alignment) -> Vec<ContentItem> {
+        let monitor = &self.monitors[monitor_index];
+        match &monitor.pages {
+            Some(pages) if alignment == Alignment::Right => pages.items.clone(),
+            _ => monitor
+                .regions
+                .iter()
+                .filter(|region| region.alignment == alignment)
+                .map(|region| region.item.clone())
+                .collect(),
+        }
+    }
+
+    /// Lay out and draw the content of all monitors again from scratch, from the items drawn so
+    /// far, and present it. For when the bars may have lost their content or its layout is
+    /// outdated. The bar keeps the items of every block, including those of all pages, so there
+    /// is no need to keep a copy just to draw them again. Needs flushing.
+    pub fn redraw(&mut self) {
+        self.relayout_monitors();
+        self.present();
+    }
+
+    /// The width of `item`, including its padding. Separators are half as wide as the bar is
+    /// high, rounded up.
+    fn cursor_offset(&self, item: &ContentItem) -> u32 {
+        let shape = match &item.alternate {
+            Some(alternate) if self.shows_alternate(item) => &alternate.shape,
+            _ => &item.shape,
+        };
+        let content_width = match shape {
+            ContentShape::Text(text) => {
+                let text = visual_order(text);
+                if item.letter_spacing == 0 {
+                    self.backend.text_width(&text, item.text_style())
+                } else {
+                    let style = item.text_style();
+                    space_letters(&self.backend, &text, style, item.letter_spacing).1
+                }
+            }
+            ContentShape::Powerline(_, _, _) => self.line.height.div_ceil(2),
+            // ContentShape::Powerline(PowerlineStyle::Octagon, _, _) => self.line.height / 4 + 1,
+            ContentShape::PageDots { count, .. } => {
+                dots_width(page_dot_size(self.line.height), *count)
+            }
+        };
+        item.padding_left + content_width + item.padding_right
+    }
+
+    /// The rectangles that `draw` would fill with `items`, relative to the monitor.
+    ///
+    /// Items are placed next to each other without gaps, in the given order, unless moved to a
+    /// tab stop. The block is then aligned as a whole: left aligned content starts at 0 and right
+    /// aligned content ends at the monitor width. Centered content is offset by half of the
+    /// remaining space, rounded down, so an odd pixel ends up on the right. Content wider than
+    /// the monitor starts at 0 regardless of the alignment and is cut off on the right.
+    /// `BarOptions::pixel_shift_interval` moves the block by a few pixels, as far as it fits.
+    #[must_use]
+    pub fn explain_layout(
+        &self,
+        monitor_index: usize,
+        alignment: Alignment,
+        items: &[ContentItem],
+    ) -> Vec<Rectangle> {
+        let row = self.layout_row(items);
+        let row_width = row.last().map_or(0, |(x, w)| x + w);
+
+        let monitor_width = self.monitors[monitor_index].w;
+        let remaining = monitor_width.saturating_sub(row_width);
+
+        // Where i start rendering depends on the alignment and the width of the content.
+        let block_offset = match alignment {
+            Alignment::Left => 0,
+            Alignment::Center => remaining / 2,
+            Alignment::Right => remaining,
+        };
+        // Shifting never pushes content past the edges of the monitor.
+        let shift = PIXEL_SHIFTS[self.pixel_shift];
+        let block_offset = block_offset.saturating_add_signed(shift).min(remaining);
+
+        row.into_iter()
+            .map(|(x, w)| Rectangle {
+                x: block_offset + x,
+                y: 0,
+                w,
+                h: self.line.height,
+            })
+            .collect()
+    }
+
+    /// The offset and width of each of `items` when placed next to each other, starting at 0.
+    fn layout_row(&self, items: &[ContentItem]) -> Vec<(u32, u32)> {
+        let cell_width = self.backend.text_width("0", TextStyle::default());
+        let mut groups = Vec::<&str>::new();
+        let columns = items.iter().map(|item| {
+            // Collapsed groups only take up the space of their first item.
+            if let Some(group) = item.group.as_deref() {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                } else if self.is_group_collapsed(group) {
+                    return (0, None);
+                }
+            }
+
+            let tab_stop = item.tab_stop.map(|tab_stop| match tab_stop {
+                TabStop::Pixels(x) => x,
+                TabStop::Cells(cells) => cells * cell_width,
+            });
+            (self.cursor_offset(item), tab_stop)
+        });
+        tab_columns(columns)
+    }
+
+    /// Draw `items` as the block of `alignment` on the monitor at `monitor_index`, replacing
+    /// what has been drawn there before, laid out as by `explain_layout`.
+    ///
+    /// A right block that doesn't fit next to the left and center blocks drawn so far is split
+    /// into pages. Only the current page is drawn, followed by a `ContentShape::PageDots` item.
+    /// Scrolling over the block or clicking the dots turns the pages, as does
+    /// `BarOptions::page_interval`. The dots have the index one past the last item.
+    pub fn draw(&mut self, monitor_index: usize, alignment: Alignment, items: &[ContentItem]) {
+        self.place(monitor_index, alignment, items);
+        self.paint_regions(monitor_index, |region| region.alignment == alignment);
+    }
+
+    /// Show `items` as the block of `alignment` on the monitor at `monitor_index` and present
+    /// the bars, instead of clearing, drawing and presenting everything.
+    ///
+    /// The new layout is compared with what is on screen, and only the areas of items that
+    /// changed, moved or went away are cleared and painted again. Nothing is presented if nothing
+    /// changed, so calling this with the same items over and over is cheap. Changing the left or
+    /// center block also lays out the right block again, since the space left for its pages
+    /// changes. Needs flushing.
+    pub fn set_content(
+        &mut self,
+        monitor_index: usize,
+        alignment: Alignment,
+        items: Vec<ContentItem>,
+    ) {
+        let before = self.painted_regions(monitor_index);
+        let right_items = (alignment != Alignment::Right)
+            .then(|| self.block_items(monitor_index, Alignment::Right))
+            .filter(|items| !items.is_empty());
+        self.place(monitor_index, alignment, &items);
+        if let Some(right_items) = right_items {
+            self.place(monitor_index, Alignment::Right, &right_items);
+        }
+        let after = self.painted_regions(monitor_index);
+
+        let dirty = before
+            .iter()
+            .filter(|region| !after.contains(region))
+            .chain(after.iter().filter(|region| !before.contains(region)))
+            .filter(|region| region.w > 0)
+            .map(|region| Rectangle {
+                x: region.x,
+                y: 0,
+                w: region.w,
+                h: self.line.height,
+            })
+            .collect::<Vec<_>>();
+        if dirty.is_empty() {
+            return;
+        }
+
+        let surface = self.monitors[monitor_index].surface;
+        self.backend.fill_rects(surface, CLEAR_COLOR, &dirty);
+        // Unchanged items overlapping the cleared areas need painting as well.
+        self.paint_regions(monitor_index, |region| {
+            dirty
+                .iter()
+                .any(|rect| rect.x < region.x + region.w && region.x < rect.x + rect.w)
+        });
+        self.present();
+    }
+
+    /// What the regions of the monitor at `monitor_index` look like on screen, for comparing
+    /// layouts in `set_content`.
+    fn painted_regions(&self, monitor_index: usize) -> Vec<PaintedRegion> {
+        self.monitors[monitor_index]
+            .regions
+            .iter()
+            .map(|region| PaintedRegion {
+                x: region.x,
+                w: region.w,
+                item: region.item.clone(),
+                alternate: region.alternate,
+                hovered: self.hovered == Some(region.location(monitor_index)),
+            })
+            .collect()
+    }
+
+    /// Paint the regions of the monitor at `monitor_index` that match `filter`, in the order in
+    /// which they were placed.
+    fn paint_regions(&mut self, monitor_index: usize, filter: impl Fn(&Region) -> bool) {
+        let monitor = &self.monitors[monitor_index];
+        for region in monitor.regions.iter().filter(|region| filter(region)) {
+            let hovered = self.hovered == Some(region.location(monitor_index));
+            draw_region(
+                &mut self.backend,
+                monitor.surface,
+                self.line,
+                region,
+                hovered,
+            );
+        }
+    }
+
+    /// Lay out `items` as the block of `alignment` on the monitor at `monitor_index`, replacing
+    /// the regions of the block without painting them.
+    fn place(&mut self, monitor_index: usize, alignment: Alignment, items: &[ContentItem]) {
+        let indexed = if alignment == Alignment::Right {
+            self.paginate(monitor_index, items)
+        } else {
+            items.iter().cloned().enumerate().collect()
+        };
+        let visible = indexed
+            .iter()
+            .map(|(_, item)| item.clone())
+            .collect::<Vec<_>>();
+        let rects = self.explain_layout(monitor_index, alignment, &visible);
+
+        let monitor = &mut self.monitors[monitor_index];
+        monitor
+            .regions
+            .retain(|region| region.alignment != alignment);
+        for ((index, item), rect) in indexed.into_iter().zip(rects) {
+            let alternate = self.shows_alternate(&item);
+            self.monitors[monitor_index].regions.push(Region {
+                alignment,
+                index,
+                x: rect.x,
+                y: rect.y,
+                w: rect.w,
+                item,
+                alternate,
+            });
+        }
+    }
+
+    /// The items of the current page of the right block `items` with their indices, followed by
+    /// the page dots, or all of them if they fit.
+    fn paginate(
+        &mut self,
+        monitor_index: usize,
+        items: &[ContentItem],
+    ) -> Vec<(usize, ContentItem)> {
+        let monitor = &self.monitors[monitor_index];
+        let taken = monitor
+            .regions
+            .iter()
+            .filter(|region| region.alignment != Alignment::Right)
+            .map(|region| region.x + region.w)
+            .max()
+            .unwrap_or(0);
+        let available = monitor.w.saturating_sub(taken);
+        let row = self.layout_row(items);
+        if row.last().map_or(0, |(x, w)| x + w) <= available {
+            self.monitors[monitor_index].pages = None;
+            return items.iter().cloned().enumerate().collect();
+        }
+
+        // The dots take away from the space of the pages, so more pages need more dots.
+        let widths = row.into_iter().map(|(_, w)| w).collect::<Vec<_>>();
+        let dot_size = page_dot_size(self.line.height);
+        let mut count = 2;
+        let starts = loop {
+            let starts = page_starts(
+                &widths,
+                available.saturating_sub(dots_width(dot_size, count)),
+            );
+            if starts.len() <= count {
+                break starts;
+            }
+            count = starts.len();
+        };
+
+        let count = starts.len();
+        let previous = self.monitors[monitor_index]
+            .pages
+            .as_ref()
+            .map_or(0, |pages| pages.page);
+        let page = previous.min(count - 1);
+        let end = starts.get(page + 1).copied().unwrap_or(items.len());
+        let mut visible = (starts[page]..end)
+            .map(|index| (index, items[index].clone()))
+            .collect::<Vec<_>>();
+        let last = &items[end - 1];
+        visible.push((
+            items.len(),
+            ContentItem {
+                fg: last.fg,
+                bg: last.bg,
+                shape: ContentShape::PageDots { page, count },
+                ..Default::default()
+            },
+        ));
+        self.monitors[monitor_index].pages = Some(Pages {
+            items: items.to_vec(),
+            page,
+            count,
+        });
+        visible
+    }
+
+    /// Show the next page of the right block on the monitor at `monitor_index`, or the previous
+    /// one if not `forward`, wrapping around. Does nothing if the block fits. Needs presenting.
+    pub fn turn_page(&mut self, monitor_index: usize, forward: bool) {
+        let Some(pages) = &mut self.monitors[monitor_index].pages else {
+            return;
+        };
+        pages.turn(forward);
+        self.relayout_monitors();
+    }
+
+    /// The direction in which pressing `button` over the item at `location` turns the pages, if
+    /// it does: scrolling over a paginated block or clicking its dots.
+    fn page_turn(&self, location: ItemLocation, button: x::Button) -> Option<bool> {
+        let pages = self.monitors[location.monitor].pages.as_ref()?;
+        if location.alignment != Alignment::Right {
+            return None;
+        }
+        match button {
+            4 => Some(false),
+            5 => Some(true),
+            1 if location.index == pages.items.len() => Some(true),
+            _ => None,
+        }
+    }
+
+    fn region(&self, location: ItemLocation) -> Option<&Region> {
+        self.monitors[location.monitor]
+            .regions
+            .iter()
+            .find(|region| region.alignment == location.alignment && region.index == location.index)
+    }
+
+    #[must_use]
+    pub fn is_group_collapsed(&self, group: &str) -> bool {
+        self.collapsed_groups
+            .iter()
+            .any(|collapsed| collapsed == group)
+    }
+
+    /// Collapse the items of `group` into the first one of them, or expand them again, see
+    /// `ContentItem::group`. The content is laid out and drawn again, but not presented.
+    pub fn set_group_collapsed(&mut self, group: &str, collapsed: bool) {
+        if self.is_group_collapsed(group) == collapsed {
+            return;
+        }
+        if collapsed {
+            self.collapsed_groups.push(group.to_owned());
+        } else {
+            self.collapsed_groups.retain(|collapsed| collapsed != group);
+        }
+        self.relayout_monitors();
+    }
+
+    /// The group that clicking the item at `location` collapses or expands. Only the first item
+    /// of a group without an `on_click` action of its own does.
+    fn toggled_group(&self, location: ItemLocation) -> Option<&str> {
+        let region = self.region(location)?;
+        let group = region.item.group.as_deref()?;
+        if region.item.on_click.is_some() {
+            return None;
+        }
+        let first = self.monitors[location.monitor]
+            .regions
+            .iter()
+            .find(|other| {
+                other.alignment == location.alignment && other.item.group.as_deref() == Some(group)
+            })?;
+        (first.index == location.index).then_some(group)
+    }
+
+    #[must_use]
+    pub fn is_alternate_shown(&self, name: &str) -> bool {
+        self.alternates_shown.iter().any(|shown| shown == name)
+    }
+
+    /// Show the alternate content of the items with the toggle `name`, or their own content
+    /// again, see `ContentItem::alternate`. The content is laid out and drawn again, but not
+    /// presented. Toggles are forgotten when the bar is dropped, clients that want them to
+    /// survive restarts can store `is_alternate_shown` and restore it on startup.
+    pub fn set_alternate_shown(&mut self, name: &str, shown: bool) {
+        if self.is_alternate_shown(name) == shown {
+            return;
+        }
+        if shown {
+            self.alternates_shown.push(name.to_owned());
+        } else {
+            self.alternates_shown.retain(|shown| shown != name);
+        }
+        self.relayout_monitors();
+    }
+
+    fn shows_alternate(&self, item: &ContentItem) -> bool {
+        item.alternate
+            .as_ref()
+            .is_some_and(|alternate| self.is_alternate_shown(&alternate.name))
+    }
+
+    /// The toggle that clicking the item at `location` switches, unless the item has an
+    /// `on_click` action.
+    fn toggled_alternate(&self, location: ItemLocation) -> Option<&str> {
+        let item = &self.region(location)?.item;
+        if item.on_click.is_some() {
+            return None;
+        }
+        item.alternate
+            .as_ref()
+            .map(|alternate| alternate.name.as_str())
+    }
+
+    /// Find the item at horizontal position `x` of the monitor at `monitor_index`.
+    fn locate(&self, monitor_index: usize, x: u32) -> Option<ItemLocation> {
+        self.monitors[monitor_index]
+            .regions
+            .iter()
+            .find(|region| region.x <= x && x < region.x + region.w)
+            .map(|region| region.location(monitor_index))
+    }
+
+    /// Redraw the item at `location`, if it exists and has a hover style.
+    /// Returns whether anything has been drawn.
+    fn repaint_hover(&mut self, location: ItemLocation, hovered: bool) -> bool {
+        let surface = self.monitors[location.monitor].surface;
+        let region = self.monitors[location.monitor]
+            .regions
+            .iter()
+            .find(|region| {
+                region.alignment == location.alignment && region.index == location.index
+            });
+        match region {
+            Some(region) if region.item.has_hover_style() => {
+                draw_region(&mut self.backend, surface, self.line, region, hovered);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Open a popup next to the item at `anchor`, showing one row per entry of `rows`.
+    ///
+    /// Only one popup can be open at a time, an already open popup is closed first. The popup is
+    /// closed automatically when clicking anywhere outside of it.
+    pub fn open_popup(
+        &mut self,
+        anchor: ItemLocation,
+        placement: PopupPlacement,
+        rows: &[Vec<ContentItem>],
+    ) {
+        self.close_popup();
+
+        let Some(anchor_x) = self.region(anchor).map(|region| region.x) else {
+            debug!("Not opening popup, anchor {anchor:?} does not exist");
+            return;
+        };
+
+        let mut regions = Vec::new();
+        for (row_index, row) in (0..).zip(rows) {
+            for (index, (item, (x, w))) in row.iter().zip(self.layout_row(row)).enumerate() {
+                regions.push(Region {
+                    alignment: Alignment::Left,
+                    index,
+                    x,
+                    y: row_index * self.line.height,
+                    w,
+                    item: item.clone(),
+                    alternate: self.shows_alternate(item),
+                });
+            }
+        }
+        let w = regions
+            .iter()
+            .map(|region| region.x + region.w)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let h = u32::try_from(rows.len()).expect("Too many popup rows") * self.line.height;
+        let h = h.max(1);
+
+        // Keep the popup within the monitor horizontally.
+        let monitor = &self.monitors[anchor.monitor];
+        let x = (monitor.x + anchor_x).min((monitor.x + monitor.w).saturating_sub(w));
+        let y = match placement {
+            PopupPlacement::Below => monitor.y + self.line.height,
+            PopupPlacement::Above => monitor.y.saturating_sub(h),
+        };
+
+        let surface = self
+            .backend
+            .create_surface(&Rectangle { x, y, w, h }, SurfaceKind::Popup);
+        let rect = Rectangle { x: 0, y: 0, w, h };
+        self.backend.fill_rects(surface, CLEAR_COLOR, &[rect]);
+        for region in &regions {
+            draw_region(&mut self.backend, surface, self.line, region, false);
+        }
+
+        self.popup = Some(Popup {
+            anchor,
+            x,
+            y,
+            w,
+            h,
+            regions,
+            close_on_click: false,
+            surface,
+        });
+        self.present_popup();
+        self.flush();
+    }
+
+    /// Ask for confirmation before running a destructive action, e.g. shutting down. Opens a
+    /// popup next to the item at `anchor` showing `question` above `Yes` and `No`.
+    ///
+    /// Clicking `Yes` reports `action` as `Event::Click` at `anchor`. Clicking anywhere else only
+    /// closes the popup.
+    pub fn confirm(
+        &mut self,
+        anchor: ItemLocation,
+        placement: PopupPlacement,
+        question: &str,
+        action: &str,
+        fg: RGBA,
+        bg: RGBA,
+    ) {
+        let text = |text: &str, on_click: Option<String>| ContentItem {
+            fg,
+            bg,
+            shape: ContentShape::Text(format!(" {text} ")),
+            on_click,
+            ..Default::default()
+        };
+        let rows = [
+            vec![text(question, None)],
+            vec![text("Yes", Some(action.to_owned())), text("No", None)],
+        ];
+        self.open_popup(anchor, placement, &rows);
+        if let Some(popup) = &mut self.popup {
+            popup.close_on_click = true;
+        }
+    }
+
+    /// Hide all bars, give back the reserved screen space and free all resources.
+    ///
+    /// Simply dropping the bar leaves it to the X server to clean up once the connection is
+    /// closed, which some window managers only notice late.
+    pub fn shutdown(mut self) {
+        self.close_popup();
+        for monitor in std::mem::take(&mut self.monitors) {
+            self.backend.set_surface_visible(monitor.surface, false);
+            self.backend.destroy_surface(monitor.surface);
+        }
+        self.flush();
+    }
+
+    /// Close the currently open popup, if any.
+    pub fn close_popup(&mut self) {
+        if let Some(popup) = self.popup.take() {
+            self.backend.destroy_surface(popup.surface);
+            self.flush();
+        }
+    }
+
+    /// The item currently under the pointer.
+    #[must_use]
+    pub fn hovered(&self) -> Option<ItemLocation> {
+        self.hovered
+    }
+
+    /// The item that the currently open popup is anchored to.
+    #[must_use]
+    pub fn popup_anchor(&self) -> Option<ItemLocation> {
+        self.popup.as_ref().map(|popup| popup.anchor)
+    }
+
+    /// The area covered by the currently open popup, in screen coordinates.
+    #[must_use]
+    pub fn popup_geometry(&self) -> Option<Rectangle> {
+        self.popup.as_ref().map(|popup| Rectangle {
+            x: popup.x,
+            y: popup.y,
+            w: popup.w,
+            h: popup.h,
+        })
+    }
+
+    /// Show the item at `location` in its hover colors, or none if `None`, and present the bars
+    /// if that changed anything. `Bar::next_event` does this as the pointer moves, this is for
+    /// other ways of pointing at items, e.g. the keyboard.
+    pub fn set_hovered(&mut self, hovered: Option<ItemLocation>) {
+        let previous = std::mem::replace(&mut self.hovered, hovered);
+        if previous == hovered {
+            return;
+        }
+        let left = previous.is_some_and(|location| self.repaint_hover(location, false));
+        let entered = hovered.is_some_and(|location| self.repaint_hover(location, true));
+        if left || entered {
+            self.present();
+            self.flush();
+        }
+    }
+
+    fn present_popup(&mut self) {
+        if let Some(popup) = &self.popup {
+            self.backend.present(&[popup.surface]);
+        }
+    }
+
+    /// Fade the bars out by one more step. Needs flushing.
+    fn dim_further(&mut self) {
+        let Some(&opacity) = DIM_STEPS.get(self.dim_step) else {
+            return;
+        };
+        self.dim_step += 1;
+        self.set_opacity(opacity);
+    }
+
+    /// Light dimmed bars up again at once. Needs flushing.
+    fn light_up(&mut self) {
+        if self.dim_step == 0 {
+            return;
+        }
+        self.dim_step = 0;
+        self.set_opacity(1.0);
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        for monitor in &self.monitors {
+            self.backend.set_surface_opacity(monitor.surface, opacity);
+        }
+    }
+
+    /// Advance to the next offset of `BarOptions::pixel_shift_interval`, if it's time to.
+    fn shift_pixels(&mut self) {
+        let Some(interval) = self.options.pixel_shift_interval else {
+            return;
+        };
+        if self.shifted_at.elapsed() < interval {
+            return;
+        }
+        self.shifted_at = Instant::now();
+        self.pixel_shift = (self.pixel_shift + 1) % PIXEL_SHIFTS.len();
+        self.relayout_monitors();
+    }
+
+    /// The current content of the bar on the monitor at `monitor_index`, e.g. for bug reports.
+    /// Save it with `Image::write_png`.
+    pub async fn screenshot(&self, monitor_index: usize) -> Image {
+        self.backend
+            .capture(self.monitors[monitor_index].surface)
+            .await
+    }
+
+    /// Turn the pages of all paginated right blocks, if `BarOptions::page_interval` has passed.
+    fn turn_pages(&mut self) {
+        let Some(interval) = self.options.page_interval else {
+            return;
+        };
+        if self.paged_at.elapsed() < interval {
+            return;
+        }
+        self.paged_at = Instant::now();
+        let mut turned = false;
+        for pages in self
+            .monitors
+            .iter_mut()
+            .filter_map(|monitor| monitor.pages.as_mut())
+        {
+            pages.turn(true);
+            turned = true;
+        }
+        if turned {
+            self.relayout_monitors();
+        }
+    }
+
+    pub fn present(&mut self) {
+        self.light_up();
+        self.shift_pixels();
+        self.turn_pages();
+        let surfaces = self
+            .monitors
+            .iter()
+            .map(|monitor| monitor.surface)
+            .collect::<Vec<_>>();
+        self.backend.present(&surfaces);
+        self.stats.frames += 1;
+    }
+
+    pub fn flush(&self) {
+        self.backend.flush();
+    }
+
+    fn is_clickable(&self, location: Option<ItemLocation>) -> bool {
+        location.is_some_and(|location| {
+            self.region(location)
+                .is_some_and(|region| region.item.on_click.is_some())
+                || self.toggled_group(location).is_some()
+                || self.toggled_alternate(location).is_some()
+                || self.page_turn(location, 1).is_some()
+        })
+    }
+}
+
+impl Bar<X11Backend> {
+    /// Report clicks on popup items with an `on_click` action, closing the popup.
+    fn handle_popup_click(&mut self, event: &xcb::Event) -> Option<Event> {
+        let popup = self.popup.as_ref()?;
+        let xcb::Event::X(x::Event::ButtonPress(press)) = event else {
+            return None;
+        };
+        if press.event() != popup.surface.window {
+            return None;
+        }
+
+        let x = u32::try_from(press.event_x()).ok()?;
+        let y = u32::try_from(press.event_y()).ok()?;
+        let height = self.line.height;
+        let action = popup
+            .regions
+            .iter()
+            .find(|region| {
+                region.x <= x && x < region.x + region.w && region.y <= y && y < region.y + height
+            })
+            .and_then(|region| region.item.on_click.clone())?;
+        let location = popup.anchor;
+        self.close_popup();
+        Some(Event::Click {
+            location,
+            button: press.detail(),
+            action,
+        })
+    }
+
+    /// Handle events concerning the popup. Returns whether the event has been consumed.
+    fn handle_popup_event(&mut self, event: &xcb::Event) -> bool {
+        let Some(popup) = &self.popup else {
+            return false;
+        };
+
+        match event {
+            xcb::Event::X(x::Event::Expose(event)) if event.window() == popup.surface.window => {
+                self.present_popup();
+                self.flush();
+                true
+            }
+            xcb::Event::X(x::Event::ButtonPress(event)) => {
+                let inside = event.event() == popup.surface.window
+                    && u32::try_from(event.event_x()).is_ok_and(|x| x < popup.w)
+                    && u32::try_from(event.event_y()).is_ok_and(|y| y < popup.h);
+                // Clicks outside only dismiss the popup, they are not passed on.
+                if !inside || popup.close_on_click {
+                    self.close_popup();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The counters collected so far.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            requests: self.stats.requests + self.backend.requests_sent(),
+            ..self.stats
+        }
+    }
+
+    /// Wait for the next X event. Fails if the connection to the X server has been lost.
+    ///
+    /// Prefer `next_event`, which also handles the completion of presentations. Bars presented
+    /// while the previous presentation is pending are only updated once it has been handled.
+    pub async fn next_x_event(&self) -> Result<xcb::Event, xcb::ConnError> {
+        self.backend.wait_for_event().await
+    }
+
+    /// Wait for the next X event like `next_x_event`, giving up at `deadline` if there is one,
+    /// or when the process is asked to terminate.
+    async fn next_x_event_before(&mut self, deadline: Option<tokio::time::Instant>) -> Wakeup {
+        let Self {
+            backend, terminate, ..
+        } = self;
+        let input = async {
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, backend.wait_for_event())
+                    .await
+                    .map_or(Wakeup::Deadline, Wakeup::X),
+                None => Wakeup::X(backend.wait_for_event().await),
+            }
+        };
+        let terminated = async {
+            match terminate {
+                Some(terminate) => {
+                    terminate.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            wakeup = input => wakeup,
+            () = terminated => Wakeup::Terminate,
+        }
+    }
+
+    /// Wait for the next X event like `next_x_event_before`, fading the bars out in the meantime
+    /// once the user has been idle for `BarOptions::dim_after`. Events don't interrupt the fade,
+    /// it continues with the next call.
+    async fn next_x_event_or_dim(&mut self, deadline: Option<tokio::time::Instant>) -> Wakeup {
+        loop {
+            let Some(dim_after) = self.options.dim_after else {
+                return self.next_x_event_before(deadline).await;
+            };
+            let wait = match self.dim_step {
+                0 => {
+                    let Some(idle) = self.backend.idle_time().await else {
+                        return self.next_x_event_before(deadline).await;
+                    };
+                    if idle >= dim_after {
+                        self.dim_further();
+                        self.flush();
+                        continue;
+                    }
+                    // Input in the meantime restarts the idle time, so check again when time is
+                    // up.
+                    dim_after - idle
+                }
+                step if step < DIM_STEPS.len() => DIM_STEP_INTERVAL,
+                _ => return self.next_x_event_before(deadline).await,
+            };
+            if let Ok(event) = tokio::time::timeout(wait, self.next_x_event_before(deadline)).await
+            {
+                return event;
+            }
+            if self.dim_step > 0 {
+                self.dim_further();
+                self.flush();
+            }
+        }
+    }
+
+    /// Register a global key combination, reported as `Event::Hotkey` when pressed.
+    ///
+    /// The combination triggers regardless of caps lock and num lock. Returns whether the key
+    /// could be grabbed, which fails if the keysym is not on the keyboard or another client
+    /// holds the combination already.
+    pub async fn grab_key(&mut self, modifiers: x::ModMask, keysym: x::Keysym) -> bool {
+        let grabs = grab_hotkey(&self.backend, Hotkey { modifiers, keysym }).await;
+        let grabbed = !grabs.is_empty();
+        self.hotkeys.extend(grabs);
+        grabbed
+    }
+
+    fn hotkey(&self, keycode: x::Keycode, state: x::KeyButMask) -> Option<Hotkey> {
+        let ignored = x::ModMask::LOCK | x::ModMask::N2;
+        let modifiers = x::ModMask::from_bits_truncate(state.bits()) - ignored;
+        self.hotkeys
+            .iter()
+            .find(|(code, hotkey)| *code == keycode && hotkey.modifiers == modifiers)
+            .map(|(_, hotkey)| *hotkey)
+    }
+
+    /// Find the item at horizontal position `x` of `window`.
+    fn locate_in_window(&self, window: x::Window, x: i16) -> Option<ItemLocation> {
+        let x = u32::try_from(x).ok()?;
+        let monitor_index = self
+            .monitors
+            .iter()
+            .position(|monitor| monitor.surface.window == window)?;
+        self.locate(monitor_index, x)
+    }
+
+    /// Show a hand cursor while hovering clickable items.
+    fn update_cursor(&self, previous: Option<ItemLocation>, current: Option<ItemLocation>) {
+        // When leaving all items, reset the cursor of the window that was left.
+        if let Some(location) = current.or(previous) {
+            let surface = self.monitors[location.monitor].surface;
+            self.backend
+                .set_hand_cursor(surface, self.is_clickable(current));
+            self.flush();
+        }
+    }
+
+    /// Connect to the X server again after the connection has been lost, e.g. because the display
+    /// manager restarted. Retries until the server is back, then recreates the windows and key
+    /// grabs and replays the content drawn so far onto monitors that still exist.
+    ///
+    /// Everything that needs awaiting happens before the bar is modified, so dropping the future
+    /// early leaves the bar as it was.
+    pub async fn reconnect(&mut self) {
+        let mut backend = loop {
+            if let Some(backend) = X11Backend::try_new().await {
+                break backend;
+            }
+            debug!("X server not reachable, retrying");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        };
+        let regions = backend.query_monitor_regions().await;
+        let primary_region = backend.query_primary_region().await;
+        let outputs = backend.query_outputs().await;
+
+        let mut hotkeys = Vec::<(x::Keycode, Hotkey)>::new();
+        for (_, hotkey) in &self.hotkeys {
+            if !hotkeys.iter().any(|(_, grabbed)| grabbed == hotkey) {
+                hotkeys.extend(grab_hotkey(&backend, *hotkey).await);
+            }
+        }
+
+        let (font_family, font_size) = self.backend.font();
+        if (font_family, font_size) != backend.font() {
+            let font_family = font_family.to_owned();
+            backend.set_font(&font_family, font_size);
+        }
+        backend.set_icon_size(self.backend.icon_size());
+
+        self.stats.requests += self.backend.requests_sent();
+        self.backend = backend;
+        self.popup = None;
+        self.hovered = None;
+        self.hotkeys = hotkeys;
+        self.primary = primary_index(&regions, primary_region.as_ref());
+
+        self.line = LineGeometry::new(self.backend.line_height(), &self.options);
+        let mut old_monitors = std::mem::replace(
+            &mut self.monitors,
+            create_monitors(&mut self.backend, self.line.height, &self.options, &regions),
+        );
+
+        assign_output_names(&mut self.monitors, &regions, &outputs);
+
+        // Outputs that have been disabled stay disabled.
+        let disabled_names = old_monitors
+            .iter()
+            .filter(|monitor| !monitor.enabled)
+            .flat_map(|monitor| monitor.names.iter().cloned())
+            .collect::<Vec<_>>();
+        for index in 0..self.monitors.len() {
+            let names = &self.monitors[index].names;
+            if names.iter().any(|name| disabled_names.contains(name)) {
+                self.set_monitor_enabled(index, false);
+            }
+        }
+
+        // Content follows its outputs, which may have come back in a different order. It is laid
+        // out again, since the monitors may have changed their widths.
+        for monitor in &mut self.monitors {
+            let old_monitor = old_monitors.iter_mut().find(|old_monitor| {
+                old_monitor
+                    .names
+                    .iter()
+                    .any(|name| monitor.names.contains(name))
+            });
+            if let Some(old_monitor) = old_monitor {
+                monitor.regions = std::mem::take(&mut old_monitor.regions);
+                monitor.pages = old_monitor.pages.take();
+            }
+        }
+        let stacking = self.stacking;
+        if stacking != Stacking::Default {
+            self.set_stacking(stacking);
+        }
+
+        self.redraw();
+        self.flush();
+    }
+
+    /// Switch to another font, e.g. to try out sizes. The bars are resized to the new line height
+    /// and their content is laid out and drawn again.
+    pub fn set_font(&mut self, font_family: &str, font_size: f32) {
+        self.backend.set_font(font_family, font_size);
+        self.recreate_surfaces();
+        let stacking = self.stacking;
+        if stacking != Stacking::Default {
+            self.set_stacking(stacking);
+        }
+        self.present();
+        self.flush();
+    }
+
+    /// Draw icons with the font at a different size than the text, e.g. to make them larger,
+    /// or at the same size again with `None`. The content is laid out and drawn again, the bars
+    /// keep their height.
+    pub fn set_icon_size(&mut self, icon_size: Option<f32>) {
+        self.backend.set_icon_size(icon_size);
+        self.close_popup();
+        self.relayout_monitors();
+        self.present();
+        self.flush();
+    }
+
+    /// Keep the bars above or below other windows. With `Stacking::Above`, bars are raised again
+    /// whenever another window covers them, for window managers that ignore dock layering.
+    pub fn set_stacking(&mut self, stacking: Stacking) {
+        self.stacking = stacking;
+        for monitor in &self.monitors {
+            self.backend.set_stacking(monitor.surface, stacking);
+        }
+        self.flush();
+    }
+
+    /// Raise the bar that has been covered, if bars are to be kept above other windows. Returns
+    /// whether the event has been consumed.
+    fn handle_visibility_event(&self, event: &xcb::Event) -> bool {
+        let xcb::Event::X(x::Event::VisibilityNotify(event)) = event else {
+            return false;
+        };
+        if self.stacking != Stacking::Above {
+            return false;
+        }
+
+        if event.state() != x::Visibility::Unobscured {
+            let monitor = self
+                .monitors
+                .iter()
+                .find(|monitor| monitor.surface.window == event.window());
+            if let Some(monitor) = monitor {
+                self.backend.raise(monitor.surface);
+                self.flush();
+            }
+        }
+        true
+    }
+
+    /// Wait for the next event, translating pointer movement into hover changes.
+    ///
+    /// Motion, enter and leave events are consumed. A `Event::Hover` is emitted only when the
+    /// item under the pointer changes. Items with a hover style are repainted and presented
+    /// automatically, and the cursor changes to a hand over items with an `on_click` action.
+    /// Clicks on such items are reported as `Event::Click`, unless `BarOptions::spawn_actions`
+    /// runs them, grabbed keys as `Event::Hotkey`. Close requests of the window manager are
+    /// reported as `Event::Close`, all other events are passed through as `Event::X`. If the
+    /// connection to the X server is lost, the bar reconnects and reports `Event::Reconnected`.
+    /// Dimmed bars light up again on pointer activity. Completed presentations are consumed,
+    /// presenting frames that had to wait for the next vertical blank.
+    pub async fn next_event(&mut self) -> Event {
+        self.next_event_before(None)
+            .await
+            .expect("Waiting without a deadline")
+    }
+
+    /// Wait for the next event like `next_event`, but return `None` once `deadline` passes while
+    /// waiting for input. Requests in flight and reconnects are always completed, which
+    /// cancelling `next_event` would abort, leaving their replies behind on the connection.
+    pub(crate) async fn next_event_before(
+        &mut self,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Option<Event> {
+        loop {
+            self.backend.grab_pointer_for_popup().await;
+            let event = match self.next_x_event_or_dim(deadline).await {
+                Wakeup::X(Ok(event)) => {
+                    self.stats.events += 1;
+                    event
+                }
+                Wakeup::X(Err(err)) => {
+                    warn!("Lost the connection to the X server; {err}");
+                    self.reconnect().await;
+                    return Some(Event::Reconnected);
+                }
+                Wakeup::Deadline => return None,
+                Wakeup::Terminate => return Some(Event::Close),
+            };
+            if self.backend.handle_present_event(&event) {
+                self.flush();
+                continue;
+            }
+            if let Some(click) = self.handle_popup_click(&event) {
+                if self.spawned(&click) {
+                    continue;
+                }
+                return Some(click);
+            }
+            if self.handle_popup_event(&event) || self.handle_visibility_event(&event) {
+                continue;
+            }
+
+            if self.dim_step > 0
+                && matches!(
+                    event,
+                    xcb::Event::X(
+                        x::Event::MotionNotify(_)
+                            | x::Event::EnterNotify(_)
+                            | x::Event::ButtonPress(_)
+                    )
+                )
+            {
+                self.light_up();
+                self.flush();
+            }
+
+            let hovered = match &event {
+                xcb::Event::X(x::Event::MotionNotify(event)) => {
+                    self.locate_in_window(event.event(), event.event_x())
+                }
+                xcb::Event::X(x::Event::EnterNotify(event)) => {
+                    self.locate_in_window(event.event(), event.event_x())
+                }
+                xcb::Event::X(x::Event::LeaveNotify(_)) => None,
+                xcb::Event::X(x::Event::ButtonPress(press)) => {
+                    let location = self.locate_in_window(press.event(), press.event_x());
+                    let turn = location.and_then(|location| {
+                        Some((location, self.page_turn(location, press.detail())?))
+                    });
+                    if let Some((location, forward)) = turn {
+                        self.turn_page(location.monitor, forward);
+                        self.present();
+                        self.flush();
+                        continue;
+                    }
+                    let group = location
+                        .and_then(|location| self.toggled_group(location))
+                        .map(str::to_owned);
+                    if let Some(group) = group {
+                        let collapsed = self.is_group_collapsed(&group);
+                        self.set_group_collapsed(&group, !collapsed);
+                        self.present();
+                        self.flush();
+                        continue;
+                    }
+                    let name = location
+                        .and_then(|location| self.toggled_alternate(location))
+                        .map(str::to_owned);
+                    if let Some(name) = name {
+                        let shown = self.is_alternate_shown(&name);
+                        self.set_alternate_shown(&name, !shown);
+                        self.present();
+                        self.flush();
+                        continue;
+                    }
+                    let action = location
+                        .and_then(|location| self.region(location))
+                        .and_then(|region| region.item.on_click.clone());
+                    let (Some(location), Some(action)) = (location, action) else {
+                        return Some(Event::X(event));
+                    };
+                    let click = Event::Click {
+                        location,
+                        button: press.detail(),
+                        action,
+                    };
+                    if self.spawned(&click) {
+                        continue;
+                    }
+                    return Some(click);
+                }
+                xcb::Event::X(x::Event::KeyPress(press)) => {
+                    return Some(match self.hotkey(press.detail(), press.state()) {
+                        Some(hotkey) => Event::Hotkey(hotkey),
+                        None => Event::X(event),
+                    });
+                }
+                xcb::Event::X(x::Event::ClientMessage(message))
+                    if self.backend.is_delete_request(message) =>
+                {
+                    return Some(Event::Close);
+                }
+                _ => return Some(Event::X(event)),
+            };
+
+            if hovered != self.hovered {
+                self.update_cursor(self.hovered, hovered);
+                self.set_hovered(hovered);
+                return Some(Event::Hover(hovered));
+            }
+        }
+    }
+
+    /// Run the action of `click` if `BarOptions::spawn_actions` is set. Returns whether it ran.
+    fn spawned(&self, click: &Event) -> bool {
+        let Event::Click {
+            location,
+            button,
+            action,
+        } = click
+        else {
+            return false;
+        };
+        if self.options.spawn_actions {
+            spawn_action(*location, *button, action);
+        }
+        self.options.spawn_actions
+    }
+
+    /// Run the event loop, calling `handler` with each event of `next_event` and with an
+    /// `Event::Tick` every `update_interval`, starting right away, until it breaks.
+    ///
+    /// Ticks are meant for updating the content, e.g. with `set_content`. They don't pile up:
+    /// if the handler is slow, the next tick is one interval after the late one. Other sources
+    /// of content, e.g. IPC, can be polled on ticks, or merged with `next_event` in a loop of
+    /// one's own. Ticks are only taken while waiting for input, so that requests in flight are
+    /// never abandoned.
+    pub async fn run(
+        &mut self,
+        update_interval: Duration,
+        mut handler: impl FnMut(&mut Self, Event) -> ControlFlow<()>,
+    ) {
+        let mut next_tick = tokio::time::Instant::now();
+        loop {
+            let event = match self.next_event_before(Some(next_tick)).await {
+                Some(event) => event,
+                None => {
+                    next_tick = tokio::time::Instant::now() + update_interval;
+                    Event::Tick
+                }
+            };
+            if handler(self, event).is_break() {
+                return;
+            }
+        }
+    }
+}
+
+/// The offset and width of items of the given widths placed next to each other, starting at 0.
+/// Items with a tab stop ahead of them start there instead, widening the item before them to
+/// reach it. Tab stops that have been passed already are ignored.
+fn tab_columns(items: impl IntoIterator<Item = (u32, Option<u32>)>) -> Vec<(u32, u32)> {
+    let mut row = Vec::<(u32, u32)>::new();
+    let mut cursor_offset = 0;
+    for (width, tab_stop) in items {
+        if let Some(tab_stop) = tab_stop.filter(|tab_stop| *tab_stop > cursor_offset) {
+            if let Some((_, previous_width)) = row.last_mut() {
+                *previous_width += tab_stop - cursor_offset;
+            }
+            cursor_offset = tab_stop;
+        }
+        row.push((cursor_offset, width));
+        cursor_offset += width;
+    }
+    row
+}
+
+/// Name the monitors after the outputs showing them, given the `regions` that the monitors were
+/// created on.
+fn assign_output_names<S>(
+    monitors: &mut [Monitor<S>],
+    regions: &[Rectangle],
+    outputs: &[(String, Rectangle)],
+) {
+    for (monitor, monitor_region) in monitors.iter_mut().zip(regions) {
+        monitor.names = outputs
+            .iter()
+            .filter(|(_, region)| {
+                (region.x, region.y, region.w)
+                    == (monitor_region.x, monitor_region.y, monitor_region.w)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+}
+
+/// Find the monitor showing `primary_region`. There is none if the primary output mirrors part
+/// of a larger one.
+fn primary_index(regions: &[Rectangle], primary_region: Option<&Rectangle>) -> Option<usize> {
+    regions
+        .iter()
+        .position(|region| Some(region) == primary_region)
+}
+
+/// Run `action` with `sh -c`, telling it where the click was through the environment. The child
+/// is waited for on a thread of its own, so that it doesn't linger as a zombie.
+fn spawn_action(location: ItemLocation, button: x::Button, action: &str) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(action)
+        .env("SAFTBAR_MONITOR", location.monitor.to_string())
+        .env("SAFTBAR_BUTTON", button.to_string())
+        .stdin(Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => warn!("Failed to run {action:?}; {err}"),
+    }
+}
+
+/// Grab `hotkey` regardless of caps lock and num lock, returning the grabbed keycodes.
+async fn grab_hotkey(backend: &X11Backend, hotkey: Hotkey) -> Vec<(x::Keycode, Hotkey)> {
+    let modifiers = IGNORED_MODIFIERS.map(|ignored| hotkey.modifiers | ignored);
+    let keycodes = backend.grab_key(&modifiers, hotkey.keysym).await;
+    keycodes
+        .into_iter()
+        .map(|keycode| (keycode, hotkey))
+        .collect()
+}
+
+/// Create a dock surface of `height` on each of `regions`, placed by `options.position` and
+/// `options.geometry`.
+fn create_monitors<B: RenderBackend>(
+    backend: &mut B,
+    height: u32,
+    options: &BarOptions,
+    regions: &[Rectangle],
+) -> Vec<Monitor<B::Surface>> {
+    debug!("Creating windows");
+    let monitors = regions
+        .iter()
+        .map(|region| {
+            let rect = options.geometry.place(options.position, height, region);
+            let kind = SurfaceKind::Dock(options.position.edge());
+            let surface = backend.create_surface(&rect, kind);
+            let Rectangle { x, y, w, .. } = rect;
+
+            Monitor {
+                names: Vec::new(),
+                x,
+                y,
+                w,
+                enabled: true,
+                regions: Vec::new(),
+                pages: None,
+                surface,
+            }
+        })
+        .collect::<Vec<_>>();
+    backend.flush();
+    monitors
+}
+
+/// The polygons of a powerline separator of `height` starting at `xl`.
+///
+/// The tip lies on the middle row for odd heights and spans the two middle rows for even
+/// heights, so the shape is symmetric either way.
+fn shape_powerline(
+    height: u32,
+    xl: u32,
+    direction: PowerlineDirection,
+    fill: PowerlineFill,
+) -> Vec<Vec<(u32, u32)>> {
+    let h = height;
+    let h_2 = h / 2;
+
+    let w = h.div_ceil(2);
+    let xr = xl + w;
+
+    let yt = 0;
+    let yb = h;
+
+    match (direction, fill) {
+        (PowerlineDirection::Left, PowerlineFill::Full) => {
+            vec![vec![
+                (xl, yt + h_2),
+                (xl, yb - h_2 - 1),
+                (xr, yb),
+                (xr, yt),
+                (xr - 1, yt),
+            ]]
+        }
+        (PowerlineDirection::Right, PowerlineFill::Full) => {
+            vec![vec![
+                (xl, yb),
+                (xr, yb - h_2 - 1),
+                (xr, yt + h_2),
+                (xl + 1, yt),
+                (xl, yt),
+            ]]
+        }
+        (PowerlineDirection::Left, PowerlineFill::No) => {
+            vec![
+                vec![(xl, yt + h_2), (xl, yt + h_2 + 1), (xr, yt), (xr - 1, yt)],
+                vec![
+                    (xl, yb - h_2 - 1),
+                    (xr, yb),
+                    (xr, yb - 1),
+                    (xl + 1, yb - h_2 - 1),
+                ],
+            ]
+        }
+        (PowerlineDirection::Right, PowerlineFill::No) => {
+            vec![
+                vec![(xl, yt), (xr, yt + h_2 + 1), (xr, yt + h_2), (xl + 1, yt)],
+                vec![
+                    (xl, yb),
+                    (xr, yb - h_2 - 1),
+                    (xr - 1, yb - h_2 - 1),
+                    (xl, yb - 1),
+                ],
+            ]
+        }
+    }
+}
+
+/// The polygons of an octagon separator of `height` starting at `xl`.
+///
+/// The diagonals are a quarter of the height, rounded down. Like with `shape_powerline`, the
+/// vertical edge covers the middle rows symmetrically for both odd and even heights.
+fn shape_octagon(
+    height: u32,
+    xl: u32,
+    direction: PowerlineDirection,
+    fill: PowerlineFill,
+) -> Vec<Vec<(u32, u32)>> {
+    // Consult a pixel editor for this.
+    // We want to use truncating division for odd numbers and get one less than the
+    // half for even numbers. Exactly half would point to the first line in the
+    // second half of the row.
+
+    let h = height;
+    let h_4 = h / 4;
+
+    let yt = 0;
+    let yb = h;
+
+    match direction {
+        PowerlineDirection::Right => {
+            let xr = xl + h_4 + 1;
+
+            match fill {
+                PowerlineFill::Full => {
+                    vec![vec![
+                        (xl, yb),
+                        (xr, yb - h_4 - 1),
+                        (xr, yt + h_4),
+                        (xl + 1, yt),
+                        (xl, yt),
+                    ]]
+                }
+                PowerlineFill::No => {
+                    vec![
+                        vec![(xl, yt), (xr, yt + h_4 + 1), (xr, yt + h_4), (xl + 1, yt)],
+                        vec![
+                            (xr - 1, yt + h_4),
+                            (xr - 1, yb - h_4),
+                            (xr, yb - h_4),
+                            (xr, yt + h_4),
+                        ],
+                        vec![
+                            (xl, yb),
+                            (xr, yb - h_4 - 1),
+                            (xr - 1, yb - h_4 - 1),
+                            (xl, yb - 1),
+                        ],
+                    ]
+                }
+            }
+        }
+        PowerlineDirection::Left => {
+            let w = h.div_ceil(2);
+            let xr = xl + w;
+            let xl = xr - h_4 - 1;
+
+            match fill {
+                PowerlineFill::Full => {
+                    vec![vec![
+                        (xl, yt + h_4),
+                        (xl, yb - h_4 - 1),
+                        (xr, yb),
+                        (xr, yt),
+                        (xr - 1, yt),
+                    ]]
+                }
+                PowerlineFill::No => {
+                    vec![
+                        vec![(xl, yt + h_4), (xl, yt + h_4 + 1), (xr, yt), (xr - 1, yt)],
+                        vec![
+                            (xl, yt + h_4),
+                            (xl, yb - h_4),
+                            (xl + 1, yb - h_4),
+                            (xl + 1, yt + h_4),
+                        ],
+                        vec![
+                            (xl, yb - h_4 - 1),
+                            (xr, yb),
+                            (xr, yb - 1),
+                            (xl + 1, yb - h_4 - 1),
+                        ],
+                    ]
+                }
+            }
+        }
+    }
+}
+
+fn shape_polys(
+    height: u32,
+    xl: u32,
+    style: PowerlineStyle,
+    direction: PowerlineDirection,
+    fill: PowerlineFill,
+) -> Vec<Vec<(u32, u32)>> {
+    match style {
+        PowerlineStyle::Powerline => shape_powerline(height, xl, direction, fill),
+        PowerlineStyle::Octagon => shape_octagon(height, xl, direction, fill),
+    }
+}
+
+/// Reorder `text` from logical to visual order with the Unicode bidi algorithm, so that
+/// right-to-left runs, e.g. Hebrew or Arabic window titles, can be drawn from left to right.
+///
+/// The base direction is taken from the first strong character. Text without right-to-left
+/// characters is returned as is.
+fn visual_order(text: &str) -> Cow<'_, str> {
+    let bidi_info = BidiInfo::new(text, None);
+    if !bidi_info.has_rtl() {
+        return Cow::Borrowed(text);
+    }
+
+    let visual = bidi_info
+        .paragraphs
+        .iter()
+        .map(|paragraph| bidi_info.reorder_line(paragraph, paragraph.range.clone()))
+        .collect::<String>();
+    Cow::Owned(visual)
+}
+
+/// Split `text` into characters placed `letter_spacing` pixels apart, returning their offsets
+/// and the total width.
+fn space_letters<'a, B: RenderBackend>(
+    backend: &B,
+    text: &'a str,
+    style: TextStyle,
+    letter_spacing: u32,
+) -> (Vec<(u32, &'a str)>, u32) {
+    let mut letters = Vec::new();
+    let mut width = 0;
+    for letter in text.graphemes(true) {
+        if !letters.is_empty() {
+            width += letter_spacing;
+        }
+        letters.push((width, letter));
+        width += backend.text_width(letter, style);
+    }
+    (letters, width)
+}
+
+/// The top, bottom, left and right edges of `rect`, `width` pixels thick.
+fn border_rects(rect: &Rectangle, width: u32) -> [Rectangle; 4] {
+    let Rectangle { x, y, w, h } = *rect;
+    let horizontal = width.min(h);
+    let vertical = width.min(w);
+    [
+        Rectangle {
+            x,
+            y,
+            w,
+            h: horizontal,
+        },
+        Rectangle {
+            x,
+            y: y + h - horizontal,
+            w,
+            h: horizontal,
+        },
+        Rectangle {
+            x,
+            y,
+            w: vertical,
+            h,
+        },
+        Rectangle {
+            x: x + w - vertical,
+            y,
+            w: vertical,
+            h,
+        },
+    ]
+}
+
+/// The side length of the page dots in a bar of `height`.
+fn page_dot_size(height: u32) -> u32 {
+    (height / 6).max(2)
+}
+
+/// The width of `count` page dots of `size`, one dot apart and with a dot of space on either
+/// side.
+fn dots_width(size: u32, count: usize) -> u32 {
+    let count = u32::try_from(count).expect("Page count not representable as u32");
+    (2 * count + 1) * size
+}
+
+/// The index of the first item of each page when filling pages of `width` with items of
+/// `widths` in order. Items wider than a page get a page of their own.
+fn page_starts(widths: &[u32], width: u32) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut used = 0;
+    for (index, item_width) in widths.iter().enumerate() {
+        if used > 0 && used + item_width > width {
+            starts.push(index);
+            used = 0;
+        }
+        used += item_width;
+    }
+    starts
+}
+
+fn draw_region<B: RenderBackend>(
+    backend: &mut B,
+    surface: B::Surface,
+    line: LineGeometry,
+    region: &Region,
+    hovered: bool,
+) {
+    // Collapsed items.
+    if region.w == 0 {
+        return;
+    }
+
+    let (fg, bg) = region.item.colors(hovered);
+    let height = line.height;
+
+    // Background color.
+    let rect = Rectangle {
+        x: region.x,
+        y: region.y,
+        w: region.w,
+        h: height,
+    };
+    backend.fill_rects(surface, bg, std::slice::from_ref(&rect));
+    if let Some(border) = region.item.border {
+        backend.fill_rects(surface, border.color, &border_rects(&rect, border.width));
+    }
+
+    let x = region.x + region.item.padding_left;
+    match region.shape() {
+        ContentShape::Text(text) => {
+            // Foreground text.
+            let y = (region.y + line.text_top).saturating_add_signed(region.item.y_offset);
+            let style = region.item.text_style();
+            let text = visual_order(text);
+            let width = if region.item.letter_spacing == 0 {
+                backend.draw_text(surface, fg, &text, style, x, y);
+                backend.text_width(&text, style)
+            } else {
+                let (letters, width) =
+                    space_letters(backend, &text, style, region.item.letter_spacing);
+                for (letter_x, letter) in letters {
+                    backend.draw_text(surface, fg, letter, style, x + letter_x, y);
+                }
+                width
+            };
+
+            if region.item.strikethrough {
+                // Centered on the x-height, so that it crosses lowercase letters as well.
+                let x_height = backend.x_height();
+                let thickness = (x_height / 8).max(1);
+                let strike = Rectangle {
+                    x,
+                    y: (y + backend.baseline()).saturating_sub((x_height + thickness) / 2),
+                    w: width,
+                    h: thickness,
+                };
+                backend.fill_rects(surface, fg, &[strike]);
+            }
+            if region.item.underline {
+                // A gap of its own thickness below the baseline, clear of most descenders' tops.
+                let thickness = (backend.x_height() / 8).max(1);
+                let underline = Rectangle {
+                    x,
+                    y: y + backend.baseline() + thickness,
+                    w: width,
+                    h: thickness,
+                };
+                backend.fill_rects(surface, fg, &[underline]);
+            }
+        }
+        ContentShape::Powerline(style, fill, direction) => {
+            let polys = shape_polys(height, x, *style, *direction, *fill)
+                .into_iter()
+                .map(|points| {
+                    let points = points.into_iter().map(|(x, y)| {
+                        (
+                            x,
+                            (y + region.y).saturating_add_signed(region.item.y_offset),
+                        )
+                    });
+                    points.collect()
+                })
+                .collect::<Vec<_>>();
+            backend.fill_polys(surface, fg, &polys);
+        }
+        ContentShape::PageDots { page, count } => {
+            let size = page_dot_size(height);
+            let y = (region.y + (height - size) / 2).saturating_add_signed(region.item.y_offset);
+            let dot = |index: u32| Rectangle {
+                x: x + (2 * index + 1) * size,
+                y,
+                w: size,
+                h: size,
+            };
+            // The other pages are hinted at with a translucent foreground.
+            let dimmed = (fg.0, fg.1, fg.2, fg.3 / 3);
+            let (current, others): (Vec<_>, Vec<_>) = (0..*count).partition(|index| index == page);
+            let dots = |indices: Vec<usize>| {
+                indices
+                    .into_iter()
+                    .map(|index| dot(u32::try_from(index).unwrap()))
+                    .collect::<Vec<_>>()
+            };
+            backend.fill_rects(surface, dimmed, &dots(others));
+            backend.fill_rects(surface, fg, &dots(current));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The separator geometry is easy to get off by a pixel when touching it, so the exact
+    // polygons are pinned for odd and even heights, starting at x = 10.
+
+    #[test]
+    fn powerline_polygons() {
+        use PowerlineDirection::{Left, Right};
+        use PowerlineFill::{Full, No};
+
+        let cases = [
+            (
+                5,
+                Left,
+                Full,
+                vec![vec![(10, 2), (10, 2), (13, 5), (13, 0), (12, 0)]],
+            ),
+            (
+                5,
+                Right,
+                Full,
+                vec![vec![(10, 5), (13, 2), (13, 2), (11, 0), (10, 0)]],
+            ),
+            (
+                5,
+                Left,
+                No,
+                vec![
+                    vec![(10, 2), (10, 3), (13, 0), (12, 0)],
+                    vec![(10, 2), (13, 5), (13, 4), (11, 2)],
+                ],
+            ),
+            (
+                5,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (13, 3), (13, 2), (11, 0)],
+                    vec![(10, 5), (13, 2), (12, 2), (10, 4)],
+                ],
+            ),
+            (
+                6,
+                Left,
+                Full,
+                vec![vec![(10, 3), (10, 2), (13, 6), (13, 0), (12, 0)]],
+            ),
+            (
+                6,
+                Right,
+                Full,
+                vec![vec![(10, 6), (13, 2), (13, 3), (11, 0), (10, 0)]],
+            ),
+            (
+                6,
+                Left,
+                No,
+                vec![
+                    vec![(10, 3), (10, 4), (13, 0), (12, 0)],
+                    vec![(10, 2), (13, 6), (13, 5), (11, 2)],
+                ],
+            ),
+            (
+                6,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (13, 4), (13, 3), (11, 0)],
+                    vec![(10, 6), (13, 2), (12, 2), (10, 5)],
+                ],
+            ),
+            (
+                15,
+                Left,
+                Full,
+                vec![vec![(10, 7), (10, 7), (18, 15), (18, 0), (17, 0)]],
+            ),
+            (
+                15,
+                Right,
+                Full,
+                vec![vec![(10, 15), (18, 7), (18, 7), (11, 0), (10, 0)]],
+            ),
+            (
+                15,
+                Left,
+                No,
+                vec![
+                    vec![(10, 7), (10, 8), (18, 0), (17, 0)],
+                    vec![(10, 7), (18, 15), (18, 14), (11, 7)],
+                ],
+            ),
+            (
+                15,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (18, 8), (18, 7), (11, 0)],
+                    vec![(10, 15), (18, 7), (17, 7), (10, 14)],
+                ],
+            ),
+            (
+                16,
+                Left,
+                Full,
+                vec![vec![(10, 8), (10, 7), (18, 16), (18, 0), (17, 0)]],
+            ),
+            (
+                16,
+                Right,
+                Full,
+                vec![vec![(10, 16), (18, 7), (18, 8), (11, 0), (10, 0)]],
+            ),
+            (
+                16,
+                Left,
+                No,
+                vec![
+                    vec![(10, 8), (10, 9), (18, 0), (17, 0)],
+                    vec![(10, 7), (18, 16), (18, 15), (11, 7)],
+                ],
+            ),
+            (
+                16,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (18, 9), (18, 8), (11, 0)],
+                    vec![(10, 16), (18, 7), (17, 7), (10, 15)],
+                ],
+            ),
+        ];
+        for (height, direction, fill, expected) in cases {
+            assert_eq!(
+                shape_powerline(height, 10, direction, fill),
+                expected,
+                "height {height}, {direction:?}, {fill:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn octagon_polygons() {
+        use PowerlineDirection::{Left, Right};
+        use PowerlineFill::{Full, No};
+
+        let cases = [
+            (
+                5,
+                Left,
+                Full,
+                vec![vec![(11, 1), (11, 3), (13, 5), (13, 0), (12, 0)]],
+            ),
+            (
+                5,
+                Right,
+                Full,
+                vec![vec![(10, 5), (12, 3), (12, 1), (11, 0), (10, 0)]],
+            ),
+            (
+                5,
+                Left,
+                No,
+                vec![
+                    vec![(11, 1), (11, 2), (13, 0), (12, 0)],
+                    vec![(11, 1), (11, 4), (12, 4), (12, 1)],
+                    vec![(11, 3), (13, 5), (13, 4), (12, 3)],
+                ],
+            ),
+            (
+                5,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (12, 2), (12, 1), (11, 0)],
+                    vec![(11, 1), (11, 4), (12, 4), (12, 1)],
+                    vec![(10, 5), (12, 3), (11, 3), (10, 4)],
+                ],
+            ),
+            (
+                6,
+                Left,
+                Full,
+                vec![vec![(11, 1), (11, 4), (13, 6), (13, 0), (12, 0)]],
+            ),
+            (
+                6,
+                Right,
+                Full,
+                vec![vec![(10, 6), (12, 4), (12, 1), (11, 0), (10, 0)]],
+            ),
+            (
+                6,
+                Left,
+                No,
+                vec![
+                    vec![(11, 1), (11, 2), (13, 0), (12, 0)],
+                    vec![(11, 1), (11, 5), (12, 5), (12, 1)],
+                    vec![(11, 4), (13, 6), (13, 5), (12, 4)],
+                ],
+            ),
+            (
+                6,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (12, 2), (12, 1), (11, 0)],
+                    vec![(11, 1), (11, 5), (12, 5), (12, 1)],
+                    vec![(10, 6), (12, 4), (11, 4), (10, 5)],
+                ],
+            ),
+            (
+                15,
+                Left,
+                Full,
+                vec![vec![(14, 3), (14, 11), (18, 15), (18, 0), (17, 0)]],
+            ),
+            (
+                15,
+                Right,
+                Full,
+                vec![vec![(10, 15), (14, 11), (14, 3), (11, 0), (10, 0)]],
+            ),
+            (
+                15,
+                Left,
+                No,
+                vec![
+                    vec![(14, 3), (14, 4), (18, 0), (17, 0)],
+                    vec![(14, 3), (14, 12), (15, 12), (15, 3)],
+                    vec![(14, 11), (18, 15), (18, 14), (15, 11)],
+                ],
+            ),
+            (
+                15,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (14, 4), (14, 3), (11, 0)],
+                    vec![(13, 3), (13, 12), (14, 12), (14, 3)],
+                    vec![(10, 15), (14, 11), (13, 11), (10, 14)],
+                ],
+            ),
+            (
+                16,
+                Left,
+                Full,
+                vec![vec![(13, 4), (13, 11), (18, 16), (18, 0), (17, 0)]],
+            ),
+            (
+                16,
+                Right,
+                Full,
+                vec![vec![(10, 16), (15, 11), (15, 4), (11, 0), (10, 0)]],
+            ),
+            (
+                16,
+                Left,
+                No,
+                vec![
+                    vec![(13, 4), (13, 5), (18, 0), (17, 0)],
+                    vec![(13, 4), (13, 12), (14, 12), (14, 4)],
+                    vec![(13, 11), (18, 16), (18, 15), (14, 11)],
+                ],
+            ),
+            (
+                16,
+                Right,
+                No,
+                vec![
+                    vec![(10, 0), (15, 5), (15, 4), (11, 0)],
+                    vec![(14, 4), (14, 12), (15, 12), (15, 4)],
+                    vec![(10, 16), (15, 11), (14, 11), (10, 15)],
+                ],
+            ),
+        ];
+        for (height, direction, fill, expected) in cases {
+            assert_eq!(
+                shape_octagon(height, 10, direction, fill),
+                expected,
+                "height {height}, {direction:?}, {fill:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn page_breaks() {
+        assert_eq!(page_starts(&[10, 20, 30, 5, 40], 35), vec![0, 2, 4]);
+        // Items wider than a page are not split off from nothing.
+        assert_eq!(page_starts(&[50, 10], 35), vec![0, 1]);
+        assert_eq!(page_starts(&[0, 50, 0, 10], 35), vec![0, 2]);
+    }
+
+    #[test]
+    fn tab_stop_columns() {
+        // Columns at 50 and 100, like a table of name, value and unit.
+        let columns = tab_columns([(30, None), (20, Some(50)), (10, None), (40, Some(100))]);
+        assert_eq!(columns, vec![(0, 50), (50, 20), (70, 30), (100, 40)]);
+
+        // Passed tab stops are ignored, the first item can be indented.
+        let columns = tab_columns([(8, Some(16)), (30, None), (10, Some(40))]);
+        assert_eq!(columns, vec![(16, 8), (24, 30), (54, 10)]);
+
+        // Collapsed items take no space.
+        let columns = tab_columns([(30, None), (0, None), (10, None)]);
+        assert_eq!(columns, vec![(0, 30), (30, 0), (30, 10)]);
+    }
+
+    #[test]
+    fn line_heights() {
+        let line = |text_height, min_height, odd_overhang| {
+            let options = BarOptions {
+                min_height,
+                odd_overhang,
+                ..BarOptions::default()
+            };
+            let line = LineGeometry::new(text_height, &options);
+            (line.height, line.text_top)
+        };
+
+        for odd_overhang in [OddOverhang::Below, OddOverhang::Above, OddOverhang::Grow] {
+            // Never lower than the text.
+            assert_eq!(line(16, 0, odd_overhang), (16, 0));
+            assert_eq!(line(17, 10, odd_overhang), (17, 0));
+            // Even overhangs are split evenly.
+            assert_eq!(line(16, 20, odd_overhang), (20, 2));
+            assert_eq!(line(17, 21, odd_overhang), (21, 2));
+        }
+
+        // Even font height, odd bar height.
+        assert_eq!(line(16, 21, OddOverhang::Below), (21, 2));
+        assert_eq!(line(16, 21, OddOverhang::Above), (21, 3));
+        assert_eq!(line(16, 21, OddOverhang::Grow), (22, 3));
+        // Odd font height, even bar height.
+        assert_eq!(line(17, 20, OddOverhang::Below), (20, 1));
+        assert_eq!(line(17, 20, OddOverhang::Above), (20, 2));
+        assert_eq!(line(17, 20, OddOverhang::Grow), (21, 2));
+    }
+
+    #[test]
+    fn geometry_override() {
+        let monitor = Rectangle {
+            x: 1920,
+            y: 0,
+            w: 2560,
+            h: 1440,
+        };
+        let full = Geometry::default().place(Position::Top, 20, &monitor);
+        assert_eq!(full, Rectangle { h: 20, ..monitor });
+
+        let geometry = Geometry {
+            x: 100,
+            y: 10,
+            w: Some(800),
+            h: None,
+        };
+        let placed = geometry.place(Position::Top, 20, &monitor);
+        assert_eq!(
+            (placed.x, placed.y, placed.w, placed.h),
+            (2020, 10, 800, 20)
+        );
+
+        // Bottom bars are offset from the bottom edge.
+        let placed = geometry.place(Position::Bottom, 20, &monitor);
+        assert_eq!(
+            (placed.x, placed.y, placed.w, placed.h),
+            (2020, 1410, 800, 20)
+        );
+        let placed = Geometry::default().place(Position::Bottom, 20, &monitor);
+        assert_eq!((placed.y, placed.w), (1420, 2560));
+
+        // Never past the right and bottom edges.
+        let geometry = Geometry {
+            x: 2000,
+            y: 1430,
+            w: Some(800),
+            h: None,
+        };
+        let placed = geometry.place(Position::Top, 20, &monitor);
+        assert_eq!((placed.y, placed.w), (1420, 560));
+        assert_eq!(geometry.place(Position::Bottom, 20, &monitor).y, 0);
+
+        // The height is exact, unlike `BarOptions::min_height`, even if it can't be centered.
+        let options = |h, odd_overhang| BarOptions {
+            min_height: 40,
+            odd_overhang,
+            geometry: Geometry {
+                h: Some(h),
+                ..Geometry::default()
+            },
+            ..BarOptions::default()
+        };
+        let line = |h, odd_overhang| {
+            let line = LineGeometry::new(16, &options(h, odd_overhang));
+            (line.height, line.text_top)
+        };
+        assert_eq!(line(20, OddOverhang::Below), (20, 2));
+        assert_eq!(line(21, OddOverhang::Grow), (21, 3));
+        assert_eq!(line(12, OddOverhang::Below), (12, 0));
+    }
+
+    #[test]
+    fn monitor_order_and_names() {
+        let rect = |x, y, w, h| Rectangle { x, y, w, h };
+        let outputs = [
+            ("DP-1".to_owned(), rect(1920, 0, 2560, 1440)),
+            ("HDMI-1".to_owned(), rect(0, 0, 1920, 1080)),
+            ("HDMI-2".to_owned(), rect(0, 0, 1920, 1080)),
+            ("eDP-1".to_owned(), rect(0, 1080, 1920, 1080)),
+        ];
+        let regions = crate::setup::filter_regions(
+            &outputs
+                .iter()
+                .map(|(_, region)| region.clone())
+                .collect::<Vec<_>>(),
+        );
+        let mut monitors = regions
+            .iter()
+            .map(|region| Monitor {
+                names: Vec::new(),
+                x: region.x,
+                y: region.y,
+                w: region.w,
+                enabled: true,
+                regions: Vec::new(),
+                pages: None,
+                surface: (),
+            })
+            .collect::<Vec<_>>();
+        assign_output_names(&mut monitors, &regions, &outputs);
+
+        let names = monitors
+            .iter()
+            .map(|monitor| monitor.names.join(","))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["HDMI-1,HDMI-2", "eDP-1", "DP-1"]);
     }
 }
@@ -0,0 +1,134 @@
+//! Anti-aliased filling of shapes via cairo-xcb, for separators that look jagged with the core
+//! protocol.
+
+use std::ffi::{c_int, c_void};
+
+use xcb::x;
+
+use crate::xft::RGBA;
+
+#[repr(C)]
+struct CairoSurface {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Cairo {
+    _private: [u8; 0],
+}
+
+/// Layout of `xcb_visualtype_t`.
+#[repr(C)]
+struct VisualType {
+    visual_id: u32,
+    class: u8,
+    bits_per_rgb_value: u8,
+    colormap_entries: u16,
+    red_mask: u32,
+    green_mask: u32,
+    blue_mask: u32,
+    pad: [u8; 4],
+}
+
+#[link(name = "cairo")]
+extern "C" {
+    fn cairo_xcb_surface_create(
+        connection: *mut c_void,
+        drawable: u32,
+        visual: *const VisualType,
+        width: c_int,
+        height: c_int,
+    ) -> *mut CairoSurface;
+    fn cairo_surface_flush(surface: *mut CairoSurface);
+    fn cairo_surface_destroy(surface: *mut CairoSurface);
+    fn cairo_create(surface: *mut CairoSurface) -> *mut Cairo;
+    fn cairo_destroy(cairo: *mut Cairo);
+    fn cairo_set_source_rgba(cairo: *mut Cairo, red: f64, green: f64, blue: f64, alpha: f64);
+    fn cairo_move_to(cairo: *mut Cairo, x: f64, y: f64);
+    fn cairo_line_to(cairo: *mut Cairo, x: f64, y: f64);
+    fn cairo_close_path(cairo: *mut Cairo);
+    fn cairo_fill(cairo: *mut Cairo);
+}
+
+/// A cairo surface drawing to a pixmap.
+pub struct Surface {
+    surface: *mut CairoSurface,
+    // Kept alive for as long as the surface, just in case cairo holds on to it.
+    _visual: Box<VisualType>,
+}
+
+impl Surface {
+    /// Draw to `pixmap` of the given size, which has `visual`.
+    ///
+    /// # Safety
+    ///
+    /// `connection` must be a valid xcb connection that outlives the surface.
+    pub unsafe fn new(
+        connection: *mut c_void,
+        pixmap: x::Pixmap,
+        visual: &x::Visualtype,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        use xcb::Xid;
+
+        let visual = Box::new(VisualType {
+            visual_id: visual.visual_id(),
+            class: visual.class() as u8,
+            bits_per_rgb_value: visual.bits_per_rgb_value(),
+            colormap_entries: visual.colormap_entries(),
+            red_mask: visual.red_mask(),
+            green_mask: visual.green_mask(),
+            blue_mask: visual.blue_mask(),
+            pad: [0; 4],
+        });
+        let surface = cairo_xcb_surface_create(
+            connection,
+            pixmap.resource_id(),
+            std::ptr::addr_of!(*visual),
+            c_int::try_from(width).expect("Width not representable as c_int"),
+            c_int::try_from(height).expect("Height not representable as c_int"),
+        );
+        Self {
+            surface,
+            _visual: visual,
+        }
+    }
+
+    /// Fill polygons, given as lists of points, with `color`, blending the anti-aliased edges
+    /// with what has been drawn before.
+    pub fn fill_polys(&self, color: RGBA, polys: &[Vec<(u32, u32)>]) {
+        let channel = |value: u8| f64::from(value) / 255.0;
+        unsafe {
+            let cairo = cairo_create(self.surface);
+            cairo_set_source_rgba(
+                cairo,
+                channel(color.0),
+                channel(color.1),
+                channel(color.2),
+                channel(color.3),
+            );
+            for points in polys {
+                let mut points = points.iter();
+                let Some(&(x, y)) = points.next() else {
+                    continue;
+                };
+                cairo_move_to(cairo, f64::from(x), f64::from(y));
+                for &(x, y) in points {
+                    cairo_line_to(cairo, f64::from(x), f64::from(y));
+                }
+                cairo_close_path(cairo);
+            }
+            cairo_fill(cairo);
+            cairo_destroy(cairo);
+            // Send the drawing before any following xcb requests.
+            cairo_surface_flush(self.surface);
+        }
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe { cairo_surface_destroy(self.surface) };
+    }
+}
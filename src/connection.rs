@@ -1,28 +1,85 @@
-use std::{ops::Deref, ptr::null};
+use std::cell::Cell;
+use std::ops::Deref;
+use std::os::fd::{AsRawFd, RawFd};
 
-pub struct Connection(xcb::Connection);
+use tokio::io::unix::AsyncFd;
+use xcb::x;
+
+pub struct Connection {
+    /// Notices data from the X server. Dropped first, before the connection closes the socket.
+    async_fd: AsyncFd<RawFd>,
+    connection: xcb::Connection,
+    /// The screen selected by `$DISPLAY`, e.g. 1 for `:0.1`.
+    screen_number: i32,
+    requests_sent: Cell<u64>,
+}
 
 impl Deref for Connection {
     type Target = xcb::Connection;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.connection
     }
 }
 
 impl Connection {
-    pub fn new() -> Self {
-        let display = unsafe { x11::xlib::XOpenDisplay(null()) };
+    /// Connect to the display named by `$DISPLAY`. Returns `None` if it can't be opened.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a tokio runtime.
+    pub fn new() -> Option<Self> {
+        let extensions = [xcb::Extension::RandR, xcb::Extension::Render];
+        // Only needed for dimming the bar when idle and for vsynced presentation.
+        let optional_extensions = [xcb::Extension::Sync, xcb::Extension::Present];
+        let (connection, screen_number) =
+            xcb::Connection::connect_with_extensions(None, &extensions, &optional_extensions)
+                .ok()?;
+        let async_fd = AsyncFd::new(connection.as_raw_fd()).expect("Failed to initialize async fd");
+
+        Some(Self {
+            async_fd,
+            connection,
+            screen_number,
+            requests_sent: Cell::new(0),
+        })
+    }
+
+    pub fn screen_number(&self) -> i32 {
+        self.screen_number
+    }
 
-        let extensions = [xcb::Extension::RandR];
-        let connection =
-            unsafe { xcb::Connection::from_xlib_display_and_extensions(display, &extensions, &[]) };
+    /// The number of requests sent through this connection.
+    pub fn requests_sent(&self) -> u64 {
+        self.requests_sent.get()
+    }
+
+    fn count_request(&self) {
+        self.requests_sent.set(self.requests_sent.get() + 1);
+    }
 
-        Self(connection)
+    /// Send a request, shadowing `xcb::Connection::send_request` to count it.
+    pub fn send_request<Request>(&self, request: &Request) -> Request::Cookie
+    where
+        Request: xcb::Request,
+    {
+        self.count_request();
+        self.connection.send_request(request)
     }
 
-    /// Execute a request and wait for the reply. Check for request completion.
-    pub fn exec<Request>(
+    /// Send a request without reply, shadowing `xcb::Connection::send_request_checked` to count
+    /// it.
+    pub fn send_request_checked<Request>(&self, request: &Request) -> xcb::VoidCookieChecked
+    where
+        Request: xcb::RequestWithoutReply,
+    {
+        self.count_request();
+        self.connection.send_request_checked(request)
+    }
+
+    /// Execute a request and wait for the reply without blocking the executor. Check for request
+    /// completion.
+    pub async fn exec_async<Request>(
         &self,
         request: &Request,
     ) -> Result<<<Request as xcb::Request>::Cookie as xcb::CookieWithReplyChecked>::Reply, xcb::Error>
@@ -30,14 +87,53 @@ impl Connection {
         Request: xcb::Request,
         <Request as xcb::Request>::Cookie: xcb::CookieWithReplyChecked,
     {
-        self.wait_for_reply(self.send_request(request))
+        self.wait_for_reply_async(self.send_request(request)).await
     }
 
-    /// Execute a request that has no reply. Check for request completion.
-    pub fn exec_<Request>(&self, request: &Request) -> Result<(), xcb::ProtocolError>
+    /// Wait for the reply of a request without blocking the executor.
+    pub async fn wait_for_reply_async<Cookie>(
+        &self,
+        cookie: Cookie,
+    ) -> Result<Cookie::Reply, xcb::Error>
     where
-        Request: xcb::RequestWithoutReply + std::fmt::Debug,
+        Cookie: xcb::CookieWithReplyChecked,
     {
-        self.send_and_check_request(request)
+        // The request may still be sitting in the output buffer.
+        self.flush()?;
+        self.poll_until(|| self.poll_for_reply(&cookie)).await
+    }
+
+    /// Execute a request that has no reply without blocking the executor. Check for request
+    /// completion.
+    pub async fn exec_async_<Request>(&self, request: &Request) -> Result<(), xcb::Error>
+    where
+        Request: xcb::RequestWithoutReply,
+    {
+        let cookie = self.send_request_checked(request);
+        // Replies come in order, so once a later one is there, so is the error of the request, if
+        // any, and checking it doesn't block.
+        self.exec_async(&x::GetInputFocus {}).await?;
+        Ok(self.check_request(cookie)?)
+    }
+
+    /// Call `poll` until it finds something, waiting for data from the X server in between.
+    /// `poll` is expected to read from the connection, like `poll_for_event` does.
+    pub async fn poll_until<T>(&self, mut poll: impl FnMut() -> Option<T>) -> T {
+        loop {
+            if let Some(value) = poll() {
+                return value;
+            }
+            let mut guard = self
+                .async_fd
+                .readable()
+                .await
+                .expect("Failed to wait for the X server");
+            if let Some(value) = poll() {
+                return value;
+            }
+            // Everything available has been read. Anything arriving after this is noticed
+            // again, and the next `poll` catches what arrived in between.
+            guard.clear_ready();
+        }
     }
 }
@@ -0,0 +1,249 @@
+//! A minimal C API, declared in `include/saftbar.h`.
+//!
+//! The API is immediate mode: collect the content of a frame with `saftbar_draw_text` and put it
+//! on screen with `saftbar_present`. Events are polled without blocking, wait for the file
+//! descriptor returned by `saftbar_fd` to become readable in between.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr::null;
+
+use crate::bar::{Alignment, Bar, BarOptions, ContentItem, ContentShape, Event, ItemLocation};
+use crate::xft::RGBA;
+
+pub const SAFTBAR_EVENT_HOVER: c_int = 0;
+pub const SAFTBAR_EVENT_CLICK: c_int = 1;
+pub const SAFTBAR_EVENT_RECONNECTED: c_int = 2;
+pub const SAFTBAR_EVENT_OTHER: c_int = 3;
+pub const SAFTBAR_EVENT_CLOSE: c_int = 4;
+
+/// An event as seen from C. Fields that don't apply to `kind` are -1 or null.
+#[repr(C)]
+pub struct SaftbarEvent {
+    pub kind: c_int,
+    pub monitor: c_int,
+    pub alignment: c_int,
+    pub index: c_int,
+    pub button: c_int,
+    /// The `on_click` action of the clicked item. Valid until the next call on the bar.
+    pub action: *const c_char,
+}
+
+impl SaftbarEvent {
+    fn new(kind: c_int, location: Option<ItemLocation>) -> Self {
+        let to_c_int = |value: usize| c_int::try_from(value).unwrap_or(-1);
+        let (monitor, alignment, index) = location.map_or((-1, -1, -1), |location| {
+            let alignment = match location.alignment {
+                Alignment::Left => 0,
+                Alignment::Center => 1,
+                Alignment::Right => 2,
+            };
+            (
+                to_c_int(location.monitor),
+                alignment,
+                to_c_int(location.index),
+            )
+        });
+
+        Self {
+            kind,
+            monitor,
+            alignment,
+            index,
+            button: -1,
+            action: null(),
+        }
+    }
+}
+
+/// The handle passed to C, owning the bar and the runtime to drive it with.
+pub struct Saftbar {
+    runtime: tokio::runtime::Runtime,
+    bar: Bar,
+    frame: Vec<(usize, Alignment, Vec<ContentItem>)>,
+    last_action: Option<CString>,
+}
+
+/// Convert `0xAARRGGBB`.
+fn color(argb: u32) -> RGBA {
+    let [a, r, g, b] = argb.to_be_bytes();
+    (r, g, b, a)
+}
+
+/// Connect to the X server and create a bar on each monitor. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn saftbar_new() -> *mut Saftbar {
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return std::ptr::null_mut();
+    };
+    let bar = runtime.block_on(Bar::try_with_options(BarOptions::default()));
+    let Some(bar) = bar else {
+        return std::ptr::null_mut();
+    };
+
+    Box::into_raw(Box::new(Saftbar {
+        runtime,
+        bar,
+        frame: Vec::new(),
+        last_action: None,
+    }))
+}
+
+/// Hide the bars, remove their struts and free all resources.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_free(bar: *mut Saftbar) {
+    if !bar.is_null() {
+        Box::from_raw(bar).bar.shutdown();
+    }
+}
+
+/// The number of monitors, i.e. valid values for the `monitor` arguments.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_monitor_count(bar: *const Saftbar) -> c_int {
+    c_int::try_from((*bar).bar.monitor_count()).unwrap_or(c_int::MAX)
+}
+
+/// The file descriptor of the X connection, readable when there may be events to poll.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_fd(bar: *const Saftbar) -> c_int {
+    (*bar).bar.backend().raw_connection_fd()
+}
+
+/// Append a text item to the next frame. `alignment` is 0 for left, 1 for center and 2 for
+/// right, colors are `0xAARRGGBB`. Clicking an item with a non-null `on_click` is reported as a
+/// click event carrying that action. Returns 0 on success, -1 on invalid arguments.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be null, `text` and `on_click` (if not null) must be
+/// null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_draw_text(
+    bar: *mut Saftbar,
+    monitor: c_int,
+    alignment: c_int,
+    text: *const c_char,
+    fg: u32,
+    bg: u32,
+    on_click: *const c_char,
+) -> c_int {
+    let handle = &mut *bar;
+    let alignment = match alignment {
+        0 => Alignment::Left,
+        1 => Alignment::Center,
+        2 => Alignment::Right,
+        _ => return -1,
+    };
+    let Some(monitor) = usize::try_from(monitor)
+        .ok()
+        .filter(|monitor| *monitor < handle.bar.monitor_count())
+    else {
+        return -1;
+    };
+    if text.is_null() {
+        return -1;
+    }
+
+    let item = ContentItem {
+        fg: color(fg),
+        bg: color(bg),
+        shape: ContentShape::Text(CStr::from_ptr(text).to_string_lossy().into_owned()),
+        on_click: (!on_click.is_null())
+            .then(|| CStr::from_ptr(on_click).to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let existing = handle
+        .frame
+        .iter_mut()
+        .find(|(m, a, _)| *m == monitor && *a == alignment);
+    match existing {
+        Some((_, _, items)) => items.push(item),
+        None => handle.frame.push((monitor, alignment, vec![item])),
+    }
+    0
+}
+
+/// Replace the content of all monitors with the items drawn since the last call and show it.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be null.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_present(bar: *mut Saftbar) {
+    let handle = &mut *bar;
+    handle.bar.clear_monitors();
+    let monitor_count = handle.bar.monitor_count();
+    for (monitor, alignment, items) in handle.frame.drain(..) {
+        // Monitors may have gone away when reconnecting.
+        if monitor < monitor_count {
+            handle.bar.draw(monitor, alignment, &items);
+        }
+    }
+    handle.bar.present();
+    handle.bar.flush();
+}
+
+/// Poll for the next event without blocking. Returns 1 and fills `event` if there was one,
+/// 0 otherwise.
+///
+/// Once the connection to the X server is lost, this blocks until the bar has reconnected and
+/// reports a reconnected event. `saftbar_fd` returns the new file descriptor afterwards.
+///
+/// # Safety
+///
+/// `bar` must come from `saftbar_new` and must not be null, `event` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn saftbar_poll_event(bar: *mut Saftbar, event: *mut SaftbarEvent) -> c_int {
+    let handle = &mut *bar;
+    let Saftbar {
+        runtime,
+        bar: inner,
+        last_action,
+        ..
+    } = handle;
+
+    // Only waiting for input is cut short, so replies in flight and reconnects are completed.
+    let deadline = tokio::time::Instant::now();
+    let Some(next) = runtime.block_on(inner.next_event_before(Some(deadline))) else {
+        return 0;
+    };
+
+    *event = match next {
+        Event::Hover(location) => SaftbarEvent::new(SAFTBAR_EVENT_HOVER, location),
+        Event::Click {
+            location,
+            button,
+            action,
+        } => {
+            let action = CString::new(action).unwrap_or_default();
+            let event = SaftbarEvent {
+                button: c_int::from(button),
+                action: action.as_ptr(),
+                ..SaftbarEvent::new(SAFTBAR_EVENT_CLICK, Some(location))
+            };
+            *last_action = Some(action);
+            event
+        }
+        Event::Reconnected => SaftbarEvent::new(SAFTBAR_EVENT_RECONNECTED, None),
+        Event::Close => SaftbarEvent::new(SAFTBAR_EVENT_CLOSE, None),
+        Event::Hotkey(_) | Event::Tick | Event::X(_) => {
+            SaftbarEvent::new(SAFTBAR_EVENT_OTHER, None)
+        }
+    };
+    1
+}
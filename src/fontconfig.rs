@@ -0,0 +1,263 @@
+//! Queries of the installed fonts, e.g. to validate a font family before loading it, and to find
+//! the file to load it from.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr::null_mut;
+
+#[repr(C)]
+struct FcPattern {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct FcObjectSet {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct FcCharSet {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct FcFontSet {
+    nfont: c_int,
+    sfont: c_int,
+    fonts: *mut *mut FcPattern,
+}
+
+#[repr(C)]
+struct FcMatrix {
+    xx: f64,
+    xy: f64,
+    yx: f64,
+    yy: f64,
+}
+
+const FC_RESULT_MATCH: c_int = 0;
+const FC_MATCH_PATTERN: c_int = 0;
+// Null-terminated object names.
+const FC_FAMILY: &[u8] = b"family\0";
+const FC_CHARSET: &[u8] = b"charset\0";
+const FC_FILE: &[u8] = b"file\0";
+const FC_INDEX: &[u8] = b"index\0";
+const FC_PIXEL_SIZE: &[u8] = b"pixelsize\0";
+const FC_EMBOLDEN: &[u8] = b"embolden\0";
+const FC_MATRIX: &[u8] = b"matrix\0";
+
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcNameParse(name: *const u8) -> *mut FcPattern;
+    fn FcPatternDestroy(pattern: *mut FcPattern);
+    fn FcPatternGetString(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut *mut u8,
+    ) -> c_int;
+    fn FcPatternGetCharSet(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut *mut FcCharSet,
+    ) -> c_int;
+    fn FcObjectSetCreate() -> *mut FcObjectSet;
+    fn FcObjectSetAdd(set: *mut FcObjectSet, object: *const c_char) -> c_int;
+    fn FcObjectSetDestroy(set: *mut FcObjectSet);
+    fn FcFontList(
+        config: *mut std::ffi::c_void,
+        pattern: *mut FcPattern,
+        objects: *mut FcObjectSet,
+    ) -> *mut FcFontSet;
+    fn FcFontSetDestroy(set: *mut FcFontSet);
+    fn FcCharSetHasChar(charset: *const FcCharSet, c: u32) -> c_int;
+    fn FcConfigSubstitute(
+        config: *mut std::ffi::c_void,
+        pattern: *mut FcPattern,
+        kind: c_int,
+    ) -> c_int;
+    fn FcDefaultSubstitute(pattern: *mut FcPattern);
+    fn FcFontMatch(
+        config: *mut std::ffi::c_void,
+        pattern: *mut FcPattern,
+        result: *mut c_int,
+    ) -> *mut FcPattern;
+    fn FcPatternGetInteger(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut c_int,
+    ) -> c_int;
+    fn FcPatternGetDouble(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut f64,
+    ) -> c_int;
+    fn FcPatternGetBool(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut c_int,
+    ) -> c_int;
+    fn FcPatternGetMatrix(
+        pattern: *const FcPattern,
+        object: *const c_char,
+        n: c_int,
+        value: *mut *mut FcMatrix,
+    ) -> c_int;
+}
+
+/// The font file that fontconfig picks for a pattern, see `match_font`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontMatch {
+    pub file: CString,
+    /// The index of the face within `file`.
+    pub index: i32,
+    pub pixel_size: f64,
+    /// Whether the font has to be made bold, because there is no bold variant.
+    pub embolden: bool,
+    /// Whether the font has to be slanted, because there is no italic variant.
+    pub oblique: bool,
+}
+
+/// Icons that every Nerd Font patches in: a devicon and the Font Awesome home and wifi icons.
+const NERD_FONT_PROBES: [u32; 3] = [0xE700, 0xF015, 0xF1EB];
+
+/// List the fonts matching the fontconfig `pattern`, e.g. `Ubuntu Mono:weight=bold`, and call
+/// `f` with each of them, retrieving only `objects`.
+fn for_each_font(pattern: &str, objects: &[&[u8]], mut f: impl FnMut(*const FcPattern)) {
+    let Ok(pattern) = CString::new(pattern) else {
+        return;
+    };
+    unsafe {
+        let pattern = FcNameParse(pattern.as_ptr().cast());
+        if pattern.is_null() {
+            return;
+        }
+        let object_set = FcObjectSetCreate();
+        for object in objects {
+            FcObjectSetAdd(object_set, object.as_ptr().cast());
+        }
+
+        let font_set = FcFontList(null_mut(), pattern, object_set);
+        if !font_set.is_null() {
+            let count = usize::try_from((*font_set).nfont).unwrap_or(0);
+            for index in 0..count {
+                f(*(*font_set).fonts.add(index));
+            }
+            FcFontSetDestroy(font_set);
+        }
+
+        FcObjectSetDestroy(object_set);
+        FcPatternDestroy(pattern);
+    }
+}
+
+/// The font that fontconfig picks for `pattern`, e.g. `Ubuntu Mono:weight=bold:size=12:dpi=96`,
+/// the way Xft does. Returns `None` if the pattern is invalid or no font is installed at all.
+#[must_use]
+pub fn match_font(pattern: &str) -> Option<FontMatch> {
+    let pattern = CString::new(pattern).ok()?;
+    unsafe {
+        let pattern = FcNameParse(pattern.as_ptr().cast());
+        if pattern.is_null() {
+            return None;
+        }
+        FcConfigSubstitute(null_mut(), pattern, FC_MATCH_PATTERN);
+        // Computes the pixel size from the size in points and the dpi.
+        FcDefaultSubstitute(pattern);
+        let mut result = FC_RESULT_MATCH;
+        let font = FcFontMatch(null_mut(), pattern, &mut result);
+        FcPatternDestroy(pattern);
+        if font.is_null() {
+            return None;
+        }
+
+        let mut file = null_mut();
+        let mut index = 0;
+        let mut pixel_size = 0.0;
+        let mut embolden = 0;
+        let mut matrix = null_mut::<FcMatrix>();
+        let found = FcPatternGetString(font, FC_FILE.as_ptr().cast(), 0, &mut file)
+            == FC_RESULT_MATCH
+            && !file.is_null()
+            && FcPatternGetDouble(font, FC_PIXEL_SIZE.as_ptr().cast(), 0, &mut pixel_size)
+                == FC_RESULT_MATCH;
+        let font_match = found.then(|| {
+            FcPatternGetInteger(font, FC_INDEX.as_ptr().cast(), 0, &mut index);
+            FcPatternGetBool(font, FC_EMBOLDEN.as_ptr().cast(), 0, &mut embolden);
+            let sheared = FcPatternGetMatrix(font, FC_MATRIX.as_ptr().cast(), 0, &mut matrix)
+                == FC_RESULT_MATCH
+                && !matrix.is_null()
+                && (*matrix).xy != 0.0;
+            FontMatch {
+                file: CStr::from_ptr(file.cast_const().cast()).to_owned(),
+                index,
+                pixel_size,
+                embolden: embolden != 0,
+                oblique: sheared,
+            }
+        });
+        FcPatternDestroy(font);
+        font_match
+    }
+}
+
+/// The installed font families matching the fontconfig `pattern`, sorted and without
+/// duplicates. An empty pattern lists all families.
+#[must_use]
+pub fn list_families(pattern: &str) -> Vec<String> {
+    let mut families = Vec::new();
+    for_each_font(pattern, &[FC_FAMILY], |font| {
+        let mut family = null_mut();
+        let result = unsafe { FcPatternGetString(font, FC_FAMILY.as_ptr().cast(), 0, &mut family) };
+        if result == FC_RESULT_MATCH && !family.is_null() {
+            let family = unsafe { CStr::from_ptr(family.cast_const().cast()) };
+            families.push(family.to_string_lossy().into_owned());
+        }
+    });
+    families.sort();
+    families.dedup();
+    families
+}
+
+/// Whether a font of `family` has the icons of a Nerd Font, which most status icons rely on.
+/// Separators are drawn as polygons and don't need them.
+#[must_use]
+pub fn has_nerd_font_icons(family: &str) -> bool {
+    // Fontconfig patterns escape these with a backslash.
+    let escaped = family
+        .chars()
+        .fold(String::with_capacity(family.len()), |mut escaped, c| {
+            if matches!(c, '\\' | ':' | '-' | ',') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        });
+
+    let mut found = false;
+    for_each_font(&escaped, &[FC_CHARSET], |font| {
+        let mut charset = null_mut();
+        let result =
+            unsafe { FcPatternGetCharSet(font, FC_CHARSET.as_ptr().cast(), 0, &mut charset) };
+        if result == FC_RESULT_MATCH && !charset.is_null() {
+            found |= NERD_FONT_PROBES
+                .iter()
+                .all(|c| unsafe { FcCharSetHasChar(charset, *c) } != 0);
+        }
+    });
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_fonts_have_no_nerd_font_icons() {
+        assert!(!has_nerd_font_icons("DejaVu Sans"));
+        assert!(!has_nerd_font_icons("No Such Font"));
+    }
+}
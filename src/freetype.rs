@@ -0,0 +1,335 @@
+//! Rasterization of glyphs with FreeType, for drawing text through the Render extension.
+
+use std::ffi::{c_char, c_int, c_long, c_short, c_uint, c_ulong, c_ushort, c_void, CStr};
+use std::ptr::null_mut;
+use std::rc::Rc;
+
+#[repr(C)]
+struct LibraryRec {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Generic {
+    data: *mut c_void,
+    finalizer: *mut c_void,
+}
+
+/// The public head of `FT_FaceRec`, up to the fields that are read.
+#[repr(C)]
+struct FaceRec {
+    num_faces: c_long,
+    face_index: c_long,
+    face_flags: c_long,
+    style_flags: c_long,
+    num_glyphs: c_long,
+    family_name: *mut c_char,
+    style_name: *mut c_char,
+    num_fixed_sizes: c_int,
+    available_sizes: *mut c_void,
+    num_charmaps: c_int,
+    charmaps: *mut c_void,
+    generic: Generic,
+    bbox: [c_long; 4],
+    units_per_em: c_ushort,
+    ascender: c_short,
+    descender: c_short,
+    height: c_short,
+    max_advance_width: c_short,
+    max_advance_height: c_short,
+    underline_position: c_short,
+    underline_thickness: c_short,
+    glyph: *mut GlyphSlotRec,
+    size: *mut SizeRec,
+}
+
+/// `FT_Size_Metrics`, lengths in 26.6 fixed point pixels.
+#[repr(C)]
+struct SizeMetrics {
+    x_ppem: c_ushort,
+    y_ppem: c_ushort,
+    x_scale: c_long,
+    y_scale: c_long,
+    ascender: c_long,
+    descender: c_long,
+    height: c_long,
+    max_advance: c_long,
+}
+
+/// The public head of `FT_SizeRec`.
+#[repr(C)]
+struct SizeRec {
+    face: *mut FaceRec,
+    generic: Generic,
+    metrics: SizeMetrics,
+}
+
+#[repr(C)]
+struct Bitmap {
+    rows: c_uint,
+    width: c_uint,
+    pitch: c_int,
+    buffer: *mut u8,
+    num_grays: c_ushort,
+    pixel_mode: u8,
+    palette_mode: u8,
+    palette: *mut c_void,
+}
+
+/// The public head of `FT_GlyphSlotRec`, up to the fields that are read.
+#[repr(C)]
+struct GlyphSlotRec {
+    library: *mut LibraryRec,
+    face: *mut FaceRec,
+    next: *mut GlyphSlotRec,
+    glyph_index: c_uint,
+    generic: Generic,
+    metrics: [c_long; 8],
+    linear_hori_advance: c_long,
+    linear_vert_advance: c_long,
+    advance: [c_long; 2],
+    format: c_uint,
+    bitmap: Bitmap,
+    bitmap_left: c_int,
+    bitmap_top: c_int,
+}
+
+const FT_LOAD_NO_HINTING: i32 = 1 << 1;
+const FT_RENDER_MODE_NORMAL: c_int = 0;
+const FT_PIXEL_MODE_MONO: u8 = 1;
+const FT_PIXEL_MODE_GRAY: u8 = 2;
+
+#[link(name = "freetype")]
+extern "C" {
+    fn FT_Init_FreeType(library: *mut *mut LibraryRec) -> c_int;
+    fn FT_Done_FreeType(library: *mut LibraryRec) -> c_int;
+    fn FT_New_Face(
+        library: *mut LibraryRec,
+        path: *const c_char,
+        index: c_long,
+        face: *mut *mut FaceRec,
+    ) -> c_int;
+    fn FT_Done_Face(face: *mut FaceRec) -> c_int;
+    fn FT_Set_Char_Size(
+        face: *mut FaceRec,
+        width: c_long,
+        height: c_long,
+        horizontal_resolution: c_uint,
+        vertical_resolution: c_uint,
+    ) -> c_int;
+    fn FT_Get_Char_Index(face: *mut FaceRec, code: c_ulong) -> c_uint;
+    fn FT_Load_Glyph(face: *mut FaceRec, index: c_uint, flags: i32) -> c_int;
+    fn FT_Render_Glyph(slot: *mut GlyphSlotRec, mode: c_int) -> c_int;
+    fn FT_GlyphSlot_Embolden(slot: *mut GlyphSlotRec);
+    fn FT_GlyphSlot_Oblique(slot: *mut GlyphSlotRec);
+}
+
+/// Smart object for `FT_Library` pointers.
+#[derive(Debug)]
+pub struct Library {
+    library: *mut LibraryRec,
+}
+
+impl Library {
+    /// Initialize FreeType. Returns `None` if that fails, e.g. for lack of memory.
+    #[must_use]
+    pub fn new() -> Option<Rc<Self>> {
+        let mut library = null_mut();
+        let error = unsafe { FT_Init_FreeType(&mut library) };
+        (error == 0).then(|| Rc::new(Self { library }))
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { FT_Done_FreeType(self.library) };
+    }
+}
+
+/// How a font is made bold or italic when it has no such variant, as decided by fontconfig.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Synthesis {
+    pub embolden: bool,
+    pub oblique: bool,
+}
+
+/// A rasterized glyph, one byte of coverage per pixel.
+#[derive(Clone, Debug, Default)]
+pub struct Glyph {
+    pub width: u32,
+    pub height: u32,
+    /// The offset of the bitmap from the pen position, `top` upwards from the baseline.
+    pub left: i32,
+    pub top: i32,
+    /// How far the pen moves to the right, rounded to pixels.
+    pub advance: i32,
+    /// `height` rows of `width` bytes.
+    pub pixels: Vec<u8>,
+}
+
+/// Smart object for `FT_Face` pointers, set to a fixed size.
+#[derive(Debug)]
+pub struct Face {
+    face: *mut FaceRec,
+    synthesis: Synthesis,
+    // Faces must be done before their library.
+    _library: Rc<Library>,
+}
+
+impl Drop for Face {
+    fn drop(&mut self) {
+        unsafe { FT_Done_Face(self.face) };
+    }
+}
+
+/// Round 26.6 fixed point pixels up.
+fn ceil_pixels(value: c_long) -> i64 {
+    (value + 63) >> 6
+}
+
+impl Face {
+    /// Load face `index` of the font file at `path`, scaled to `pixel_size`. Returns `None` if
+    /// the file can't be loaded or scaled.
+    #[must_use]
+    pub fn new(
+        library: &Rc<Library>,
+        path: &CStr,
+        index: i32,
+        pixel_size: f64,
+        synthesis: Synthesis,
+    ) -> Option<Self> {
+        let mut face = null_mut();
+        let error = unsafe {
+            FT_New_Face(
+                library.library,
+                path.as_ptr(),
+                c_long::from(index),
+                &mut face,
+            )
+        };
+        if error != 0 {
+            return None;
+        }
+        let face = Self {
+            face,
+            synthesis,
+            _library: Rc::clone(library),
+        };
+
+        // In 26.6 fixed point, at 72 dpi points are pixels.
+        #[allow(clippy::cast_possible_truncation)]
+        let size = (pixel_size * 64.0).round() as c_long;
+        let error = unsafe { FT_Set_Char_Size(face.face, 0, size, 72, 72) };
+        (error == 0).then_some(face)
+    }
+
+    fn size_metrics(&self) -> &SizeMetrics {
+        unsafe { &(*(*self.face).size).metrics }
+    }
+
+    /// The height of the line above the baseline, rounded up to whole pixels.
+    #[must_use]
+    pub fn ascent(&self) -> u32 {
+        u32::try_from(ceil_pixels(self.size_metrics().ascender)).unwrap_or(0)
+    }
+
+    /// The depth of the line below the baseline, rounded up to whole pixels.
+    #[must_use]
+    pub fn descent(&self) -> u32 {
+        u32::try_from(ceil_pixels(-self.size_metrics().descender)).unwrap_or(0)
+    }
+
+    /// The index of the glyph of `c`, 0 for the missing glyph.
+    #[must_use]
+    pub fn glyph_index(&self, c: char) -> u32 {
+        unsafe { FT_Get_Char_Index(self.face, c_ulong::from(u32::from(c))) }
+    }
+
+    /// Rasterize the glyph at `index` with anti-aliasing and without hinting. Glyphs in pixel
+    /// formats other than coverage, e.g. color emoji, come out empty but still advance the pen.
+    #[must_use]
+    pub fn render(&self, index: u32) -> Option<Glyph> {
+        unsafe {
+            if FT_Load_Glyph(self.face, index, FT_LOAD_NO_HINTING) != 0 {
+                return None;
+            }
+            let slot = (*self.face).glyph;
+            if self.synthesis.embolden {
+                FT_GlyphSlot_Embolden(slot);
+            }
+            if self.synthesis.oblique {
+                FT_GlyphSlot_Oblique(slot);
+            }
+            if FT_Render_Glyph(slot, FT_RENDER_MODE_NORMAL) != 0 {
+                return None;
+            }
+
+            let slot = &*slot;
+            let advance = i32::try_from((slot.advance[0] + 32) >> 6).unwrap_or(0);
+            let bitmap = &slot.bitmap;
+            let (width, height) = (bitmap.width, bitmap.rows);
+            let mut glyph = Glyph {
+                left: slot.bitmap_left,
+                top: slot.bitmap_top,
+                advance,
+                ..Glyph::default()
+            };
+            if !matches!(bitmap.pixel_mode, FT_PIXEL_MODE_GRAY | FT_PIXEL_MODE_MONO)
+                || bitmap.buffer.is_null()
+            {
+                return Some(glyph);
+            }
+
+            let pitch = isize::try_from(bitmap.pitch).unwrap_or(0);
+            let mut pixels = Vec::with_capacity((width * height) as usize);
+            for row in 0..height {
+                let start = bitmap.buffer.offset(pitch * row as isize);
+                for column in 0..width as usize {
+                    let coverage = if bitmap.pixel_mode == FT_PIXEL_MODE_MONO {
+                        let byte = *start.add(column / 8);
+                        if byte & (0x80 >> (column % 8)) == 0 {
+                            0
+                        } else {
+                            255
+                        }
+                    } else {
+                        *start.add(column)
+                    };
+                    pixels.push(coverage);
+                }
+            }
+            glyph.width = width;
+            glyph.height = height;
+            glyph.pixels = pixels;
+            Some(glyph)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_glyphs() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/golden/DejaVuSansMono.ttf\0"
+        );
+        let path = CStr::from_bytes_with_nul(path.as_bytes()).unwrap();
+        let library = Library::new().unwrap();
+        let face = Face::new(&library, path, 0, 16.0, Synthesis::default()).unwrap();
+        assert!(face.ascent() > face.descent());
+
+        // Monospace: every glyph advances the pen equally.
+        let x = face.render(face.glyph_index('x')).unwrap();
+        let m = face.render(face.glyph_index('M')).unwrap();
+        assert_eq!(x.advance, m.advance);
+        assert!(x.height < m.height);
+        assert_eq!(x.pixels.len(), (x.width * x.height) as usize);
+        assert!(x.pixels.contains(&255));
+
+        let space = face.render(face.glyph_index(' ')).unwrap();
+        assert_eq!((space.width, space.advance), (0, x.advance));
+    }
+}
@@ -1,4 +1,12 @@
+pub mod backend;
 pub mod bar;
+#[cfg(feature = "cairo")]
+mod cairo;
 mod connection;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fontconfig;
+mod freetype;
+pub mod markup;
 mod setup;
 pub mod xft;
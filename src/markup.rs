@@ -0,0 +1,313 @@
+//! A subset of Pango markup, as emitted by many status generators.
+//!
+//! `<span>` sets the colors of the text it encloses with the `foreground` and `background`
+//! attributes, or their aliases `fgcolor`, `color`, `fg`, `bgcolor` and `bg`. Colors are given as
+//! `#rgb`, `#rrggbb` or `#rrggbbaa`. `weight='bold'` and `style='italic'` (or `oblique`) select
+//! the variant of the font, as do the tags `b` and `i`. `strikethrough='true'` and the tag `s`
+//! cross out text, `underline` with any value but `none` and the tag `u` underline it. Spans
+//! nest. Other attributes (e.g. `font`) and the convenience tags `tt`, `big`, `small`, `sub` and
+//! `sup` are accepted but have no effect, since items are drawn in one font size.
+
+use crate::backend::{Slant, Weight};
+use crate::bar::{ContentItem, ContentShape};
+use crate::xft::RGBA;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MarkupError {
+    /// A `<` without matching `>`.
+    UnterminatedTag,
+    /// A tag that is neither `span` nor one of the convenience tags.
+    UnknownTag(String),
+    /// A closing tag that doesn't match the innermost open one.
+    UnexpectedClosingTag(String),
+    /// A tag that hasn't been closed at the end of the markup.
+    UnclosedTag(String),
+    /// An attribute not of the form `name='value'` or `name="value"`.
+    InvalidAttribute(String),
+    InvalidColor(String),
+    /// An entity other than `&amp;`, `&lt;`, `&gt;`, `&quot;` and `&apos;`.
+    UnknownEntity(String),
+}
+
+impl std::fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnterminatedTag => write!(f, "Unterminated tag"),
+            Self::UnknownTag(tag) => write!(f, "Unknown tag <{tag}>"),
+            Self::UnexpectedClosingTag(tag) => write!(f, "Unexpected closing tag </{tag}>"),
+            Self::UnclosedTag(tag) => write!(f, "Tag <{tag}> is never closed"),
+            Self::InvalidAttribute(attribute) => write!(f, "Invalid attribute {attribute}"),
+            Self::InvalidColor(color) => write!(f, "Invalid color {color}"),
+            Self::UnknownEntity(entity) => write!(f, "Unknown entity &{entity};"),
+        }
+    }
+}
+
+impl std::error::Error for MarkupError {}
+
+const CONVENIENCE_TAGS: [&str; 9] = ["b", "i", "s", "u", "tt", "big", "small", "sub", "sup"];
+
+/// The attributes in effect for a run of text.
+#[derive(Clone, Copy)]
+struct Style {
+    fg: RGBA,
+    bg: RGBA,
+    weight: Weight,
+    slant: Slant,
+    strikethrough: bool,
+    underline: bool,
+}
+
+/// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`.
+fn parse_color(color: &str) -> Result<RGBA, MarkupError> {
+    let invalid = || MarkupError::InvalidColor(color.to_owned());
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let channel = |index: usize, digits: usize| {
+        let value = u8::from_str_radix(&hex[index * digits..(index + 1) * digits], 16).unwrap();
+        // Repeat a single digit, `f` is `ff`.
+        if digits == 1 {
+            value * 17
+        } else {
+            value
+        }
+    };
+    match hex.len() {
+        3 => Ok((channel(0, 1), channel(1, 1), channel(2, 1), 255)),
+        6 => Ok((channel(0, 2), channel(1, 2), channel(2, 2), 255)),
+        8 => Ok((channel(0, 2), channel(1, 2), channel(2, 2), channel(3, 2))),
+        _ => Err(invalid()),
+    }
+}
+
+/// Replace the entities in `text`.
+fn unescape(text: &str) -> Result<String, MarkupError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(';')
+            .ok_or_else(|| MarkupError::UnknownEntity(rest.to_owned()))?;
+        result.push(match &rest[..end] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            entity => return Err(MarkupError::UnknownEntity(entity.to_owned())),
+        });
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Split `name='value' name="value" ...` into its pairs.
+fn parse_attributes(mut attributes: &str) -> Result<Vec<(&str, &str)>, MarkupError> {
+    let mut pairs = Vec::new();
+    loop {
+        attributes = attributes.trim_start();
+        if attributes.is_empty() {
+            return Ok(pairs);
+        }
+
+        let invalid = || MarkupError::InvalidAttribute(attributes.to_owned());
+        let (name, rest) = attributes.split_once('=').ok_or_else(invalid)?;
+        let rest = rest.trim_start();
+        let quote = rest
+            .chars()
+            .next()
+            .filter(|c| *c == '\'' || *c == '"')
+            .ok_or_else(invalid)?;
+        let (value, rest) = rest[1..].split_once(quote).ok_or_else(invalid)?;
+        pairs.push((name.trim(), value));
+        attributes = rest;
+    }
+}
+
+/// Expand `markup` into one item per run of text, starting out with the colors `fg` and `bg`.
+///
+/// # Errors
+///
+/// Fails on malformed markup, unknown tags and entities, and invalid colors.
+pub fn parse_markup(markup: &str, fg: RGBA, bg: RGBA) -> Result<Vec<ContentItem>, MarkupError> {
+    let mut items = Vec::new();
+    let outer = Style {
+        fg,
+        bg,
+        weight: Weight::Regular,
+        slant: Slant::Roman,
+        strikethrough: false,
+        underline: false,
+    };
+    // Open tags with the style in effect inside of them.
+    let mut stack: Vec<(&str, Style)> = Vec::new();
+    let mut style = outer;
+
+    let mut rest = markup;
+    loop {
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        let text = unescape(&rest[..text_end])?;
+        if !text.is_empty() {
+            items.push(ContentItem {
+                fg: style.fg,
+                bg: style.bg,
+                shape: ContentShape::Text(text),
+                weight: style.weight,
+                slant: style.slant,
+                strikethrough: style.strikethrough,
+                underline: style.underline,
+                ..Default::default()
+            });
+        }
+        rest = &rest[text_end..];
+        if rest.is_empty() {
+            break;
+        }
+
+        let tag_end = rest.find('>').ok_or(MarkupError::UnterminatedTag)?;
+        let tag = &rest[1..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match stack.pop() {
+                Some((open, _)) if open == name => {}
+                _ => return Err(MarkupError::UnexpectedClosingTag(name.to_owned())),
+            }
+            style = stack.last().map_or(outer, |(_, style)| *style);
+            continue;
+        }
+
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        match name {
+            "span" => {
+                for (attribute, value) in parse_attributes(attributes)? {
+                    match (attribute, value) {
+                        ("foreground" | "fgcolor" | "color" | "fg", _) => {
+                            style.fg = parse_color(value)?;
+                        }
+                        ("background" | "bgcolor" | "bg", _) => style.bg = parse_color(value)?,
+                        ("weight", "bold" | "ultrabold" | "heavy") => style.weight = Weight::Bold,
+                        ("weight", _) => style.weight = Weight::Regular,
+                        ("style", "italic" | "oblique") => style.slant = Slant::Italic,
+                        ("style", _) => style.slant = Slant::Roman,
+                        ("strikethrough", value) => style.strikethrough = value == "true",
+                        ("underline", value) => style.underline = value != "none",
+                        _ => {}
+                    }
+                }
+            }
+            "b" => style.weight = Weight::Bold,
+            "i" => style.slant = Slant::Italic,
+            "s" => style.strikethrough = true,
+            "u" => style.underline = true,
+            _ if CONVENIENCE_TAGS.contains(&name) => {}
+            _ => return Err(MarkupError::UnknownTag(name.to_owned())),
+        }
+        stack.push((name, style));
+    }
+
+    match stack.pop() {
+        Some((name, _)) => Err(MarkupError::UnclosedTag(name.to_owned())),
+        None => Ok(items),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: RGBA = (255, 255, 255, 255);
+    const BLACK: RGBA = (0, 0, 0, 255);
+
+    fn colored_texts(items: &[ContentItem]) -> Vec<(&str, RGBA, RGBA)> {
+        items
+            .iter()
+            .map(|item| match &item.shape {
+                ContentShape::Text(text) => (text.as_str(), item.fg, item.bg),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_spans() {
+        let markup = "a <span fg='#f00' bg=\"#00000080\">b <b>c</b> \
+                      <span background='#0000ff'>d</span></span> e";
+        let items = parse_markup(markup, WHITE, BLACK).unwrap();
+        let red = (255, 0, 0, 255);
+        let translucent = (0, 0, 0, 128);
+        assert_eq!(
+            colored_texts(&items),
+            vec![
+                ("a ", WHITE, BLACK),
+                ("b ", red, translucent),
+                ("c", red, translucent),
+                (" ", red, translucent),
+                ("d", red, (0, 0, 255, 255)),
+                (" e", WHITE, BLACK),
+            ]
+        );
+    }
+
+    #[test]
+    fn entities_and_ignored_attributes() {
+        let items = parse_markup("<span font='Sans 12'>&lt;&amp;&gt;</span>", WHITE, BLACK);
+        assert_eq!(colored_texts(&items.unwrap()), vec![("<&>", WHITE, BLACK)]);
+    }
+
+    #[test]
+    fn strikethrough() {
+        let items = parse_markup(
+            "<s>a<span strikethrough='false'>b</span></s><span strikethrough='true'>c</span>",
+            WHITE,
+            BLACK,
+        )
+        .unwrap();
+        let struck = items
+            .iter()
+            .map(|item| item.strikethrough)
+            .collect::<Vec<_>>();
+        assert_eq!(struck, vec![true, false, true]);
+    }
+
+    #[test]
+    fn underline() {
+        let items = parse_markup(
+            "<u>a</u><span underline='double'>b</span><span underline='none'>c</span>",
+            WHITE,
+            BLACK,
+        )
+        .unwrap();
+        let underlined = items.iter().map(|item| item.underline).collect::<Vec<_>>();
+        assert_eq!(underlined, vec![true, true, false]);
+    }
+
+    #[test]
+    fn malformed_markup() {
+        let parse = |markup| parse_markup(markup, WHITE, BLACK).err();
+        assert_eq!(parse("<span"), Some(MarkupError::UnterminatedTag));
+        assert_eq!(
+            parse("<b>a</i>"),
+            Some(MarkupError::UnexpectedClosingTag("i".to_owned()))
+        );
+        assert_eq!(
+            parse("<b>a"),
+            Some(MarkupError::UnclosedTag("b".to_owned()))
+        );
+        assert_eq!(
+            parse("<span fg='red'>a</span>"),
+            Some(MarkupError::InvalidColor("red".to_owned()))
+        );
+        assert_eq!(
+            parse("&nbsp;"),
+            Some(MarkupError::UnknownEntity("nbsp".to_owned()))
+        );
+    }
+}
@@ -1,20 +1,13 @@
+use std::cmp::Ordering;
 use std::os::fd::{AsRawFd, RawFd};
-use std::{cmp::Ordering, ptr::null_mut};
 
+use crate::backend::Rectangle;
 use crate::connection::Connection;
-use crate::xft::Xft;
+use crate::xft::{Xft, RGBA};
 
-use log::debug;
+use log::{debug, error};
 use xcb::Xid;
-use xcb::{randr, x};
-
-#[derive(Clone, Debug)]
-pub struct Rectangle {
-    pub x: u32,
-    pub y: u32,
-    pub w: u32,
-    pub h: u32,
-}
+use xcb::{present, randr, render, sync, x};
 
 impl From<&randr::GetCrtcInfoReply> for Rectangle {
     fn from(value: &randr::GetCrtcInfoReply) -> Self {
@@ -36,14 +29,41 @@ impl Rectangle {
     }
 }
 
-// Order rects from left to right, then from top to bottom.
-// Edge cases for overlapping screens.
+/// Order rects from left to right by their left edges, then from top to bottom by their top
+/// edges. This decides the indices of the monitors of a bar.
+///
+/// Overlapping rects are ordered by their top left corners just the same, regardless of how
+/// far they overlap. Rects with the same corner are ordered by width and then height, so the
+/// order is total: sorting is deterministic and only equal rects compare as equal.
 pub fn compare_rectangles(a: &Rectangle, b: &Rectangle) -> Ordering {
-    if a.x == b.x {
-        (a.y + a.h).cmp(&b.y)
-    } else {
-        a.x.cmp(&b.x)
+    (a.x, a.y, a.w, a.h).cmp(&(b.x, b.y, b.w, b.h))
+}
+
+/// Filter and sort crtc regions.
+///
+/// Regions inside of other regions are dropped, as they would get a bar on top of another bar.
+/// Of identical regions (mirrored outputs) only one is kept.
+pub fn filter_regions(regions: &[Rectangle]) -> Vec<Rectangle> {
+    let mut unique_regions = Vec::<Rectangle>::new();
+    for rect in regions {
+        if !unique_regions.contains(rect) {
+            unique_regions.push(rect.clone());
+        }
     }
+
+    let mut valid_regions = unique_regions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, rect)| {
+            unique_regions
+                .iter()
+                .enumerate()
+                .all(|(index_other, other)| index == index_other || !rect.is_inside(other))
+                .then_some(rect.clone())
+        })
+        .collect::<Vec<_>>();
+    valid_regions.sort_by(compare_rectangles);
+    valid_regions
 }
 
 #[derive(Debug)]
@@ -51,8 +71,30 @@ pub enum PropertyData<'a> {
     Cardinal(&'a [u32]),
     Atom(&'a [x::Atom]),
     String(&'a [u8]),
+    /// The 18 fields of ICCCM's `WM_SIZE_HINTS`.
+    SizeHints(&'a [u32; 18]),
 }
 
+/// Keeps `PolyFillRectangle` requests (8 bytes per rectangle) well below the maximum request length
+/// of 256KiB that every server supports without the BIG-REQUESTS extension.
+const MAX_RECTANGLES_PER_REQUEST: usize = 16384;
+
+/// A pipelined request that the X server rejected.
+#[derive(Debug)]
+pub struct RequestError {
+    /// The data the request has been built from.
+    pub request: String,
+    pub error: xcb::ProtocolError,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request failed: {}; {}", self.request, self.error)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
 // The following are structs holding the data for a pipelined version of the respective request.
 
 #[derive(Debug)]
@@ -61,51 +103,88 @@ pub struct ChangeProperty<'a>(pub x::Atom, pub PropertyData<'a>);
 #[derive(Debug)]
 pub struct MapWindow(pub x::Window);
 
+#[derive(Debug)]
+pub struct UnmapWindow(pub x::Window);
+
 #[derive(Debug)]
 pub struct FillRect(
-    pub x::Drawable,
-    pub x::Gcontext,
+    pub render::Picture,
+    pub RGBA,
+    pub u32,
     pub u32,
     pub u32,
     pub u32,
+);
+
+/// A glyph for a glyph set: its id, metrics and rows of coverage, each padded to 4 bytes.
+#[derive(Debug)]
+pub struct AddGlyph(
+    pub render::Glyphset,
     pub u32,
+    pub render::Glyphinfo,
+    pub Vec<u8>,
+);
+
+/// Glyphs drawn in the color of the source picture onto the destination picture, starting at the
+/// given baseline position.
+#[derive(Debug)]
+pub struct CompositeGlyphs(
+    pub render::Picture,
+    pub render::Picture,
+    pub render::Glyphset,
+    pub i32,
+    pub i32,
+    pub Vec<u32>,
 );
 
+#[derive(Debug)]
+pub struct PresentPixmap(pub x::Window, pub x::Pixmap, pub u32);
+
 #[derive(Debug)]
 pub struct FillPoly(pub x::Drawable, pub x::Gcontext, pub Vec<(u32, u32)>);
 
 #[derive(Debug)]
 pub struct CopyArea(
     pub x::Pixmap,
-    pub x::Window,
+    pub x::Drawable,
     pub x::Gcontext,
     pub u32,
     pub u32,
 );
 
 pub struct Setup {
-    // width: u32,
-    // height: u32,
+    width: u32,
+    height: u32,
 
     // Note the reverse drop order! Children first.
     pub colormap: x::Colormap,
-    visual: *mut x11::xlib::Visual,
     visual_id: u32,
+    /// The render format matching `visual_id`.
+    pict_format: render::Pictformat,
+    /// The render format of glyphs, 8 bits of coverage.
+    a8_format: render::Pictformat,
+    /// The resolution that font sizes in points are converted to pixels with.
+    dpi: f64,
     root_window: x::Window,
+    check_requests: bool,
     connection: Connection,
 }
 
 impl Setup {
-    /// Create the basic setup for dealing with windows.
-    pub fn new() -> Self {
-        let connection = Connection::new();
+    /// Create the basic setup for dealing with windows. Returns `None` if the X server can't be
+    /// reached.
+    pub async fn new() -> Option<Self> {
+        let connection = Connection::new()?;
 
         // How the layout looks like.
         let setup_info = connection.get_setup();
-        assert_eq!(setup_info.roots().count(), 1);
 
-        // The root screen - rendering canvas.
-        let screen = setup_info.roots().next().expect("Failed to get 0th screen");
+        // The root screen - rendering canvas. Displays with multiple screens (not Xinerama) get a
+        // bar on the screen selected via `$DISPLAY`, e.g. `:0.1` for the second one.
+        let screen = usize::try_from(connection.screen_number())
+            .ok()
+            .and_then(|index| setup_info.roots().nth(index))
+            .expect("Failed to get the screen selected by $DISPLAY");
 
         // The root window, which is essentially a rect.
         let root_window = screen.root();
@@ -114,131 +193,280 @@ impl Setup {
             .find_map(|depth| (depth.depth() == 32).then(|| depth.visuals()[0].visual_id()))
             .expect("Failed to find 32bit depth visual");
 
-        let display = connection.get_raw_dpy();
-        let mut visual_info_mask = x11::xlib::XVisualInfo {
-            depth: 32,
-            visual: null_mut(),
-            visualid: 0, // TODO: Specify the id we got already?
-            screen: 0,
-            class: 0,
-            red_mask: 0,
-            green_mask: 0,
-            blue_mask: 0,
-            colormap_size: 0,
-            bits_per_rgb: 0,
-        };
-        let mut result = 0;
-        let visual_info = unsafe {
-            x11::xlib::XGetVisualInfo(
-                display,
-                x11::xlib::VisualDepthMask,
-                std::ptr::addr_of_mut!(visual_info_mask),
-                std::ptr::addr_of_mut!(result),
-            )
-        };
-        let visual_info = unsafe { *visual_info };
-        assert_eq!(visual_info.visualid, u64::from(visual_id));
-        let visual = visual_info.visual;
-
-        // let width = u32::from(screen.width_in_pixels());
-        // let height = u32::from(screen.height_in_pixels());
+        let width = u32::from(screen.width_in_pixels());
+        let height = u32::from(screen.height_in_pixels());
 
         let colormap: x::Colormap = connection.generate_id();
-        connection
-            .exec_(&x::CreateColormap {
-                alloc: x::ColormapAlloc::None,
-                mid: colormap,
+        // Like all requests without a reply, errors show up in the event queue.
+        connection.send_request(&x::CreateColormap {
+            alloc: x::ColormapAlloc::None,
+            mid: colormap,
+            window: root_window,
+            visual: visual_id,
+        });
+
+        let pict_formats = connection
+            .exec_async(&render::QueryPictFormats {})
+            .await
+            .expect("Failed to query picture formats");
+        let pict_format = pict_formats
+            .screens()
+            .flat_map(|screen| screen.depths())
+            .find_map(|depth| {
+                depth
+                    .visuals()
+                    .iter()
+                    .find(|pict_visual| pict_visual.visual == visual_id)
+                    .map(|pict_visual| pict_visual.format)
+            })
+            .expect("Failed to find the picture format of the 32bit visual");
+        let a8_format = pict_formats
+            .formats()
+            .iter()
+            .find(|format| {
+                let direct = format.direct();
+                format.r#type() == render::PictType::Direct
+                    && format.depth() == 8
+                    && direct.alpha_mask == 0xff
+                    && direct.alpha_shift == 0
+            })
+            .map(render::Pictforminfo::id)
+            .expect("Failed to find the picture format of glyphs");
+
+        // Like Xft, prefer the `Xft.dpi` resource over the physical resolution of the screen.
+        let resources = connection
+            .exec_async(&x::GetProperty {
+                delete: false,
                 window: root_window,
-                visual: visual_id,
+                property: x::ATOM_RESOURCE_MANAGER,
+                r#type: x::ATOM_STRING,
+                long_offset: 0,
+                long_length: 1 << 16,
             })
-            .expect("Failed to create colormap");
+            .await
+            .ok();
+        let dpi = resources
+            .and_then(|reply| parse_xft_dpi(&String::from_utf8_lossy(reply.value::<u8>())))
+            .unwrap_or_else(|| {
+                let millimeters = screen.height_in_millimeters();
+                if millimeters == 0 {
+                    96.0
+                } else {
+                    f64::from(screen.height_in_pixels()) * 25.4 / f64::from(millimeters)
+                }
+            });
 
-        Self {
-            // width,
-            // height,
+        Some(Self {
+            width,
+            height,
             colormap,
-            visual,
             visual_id,
+            pict_format,
+            a8_format,
+            dpi,
             root_window,
+            // Checking a request costs a round trip, only do so when debugging.
+            check_requests: cfg!(debug_assertions)
+                || std::env::var_os("SAFTBAR_CHECK_REQUESTS").is_some(),
             connection,
+        })
+    }
+
+    pub fn requests_sent(&self) -> u64 {
+        self.connection.requests_sent()
+    }
+
+    /// The size of the root window, which struts are measured from.
+    pub fn screen_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Poll for the next event. Fails if the connection to the X server has been lost.
+    pub fn poll_for_event(&self) -> Result<Option<xcb::Event>, xcb::ConnError> {
+        loop {
+            match self.connection.poll_for_event() {
+                Ok(event) => return Ok(event),
+                // Errors of unchecked requests are reported here instead.
+                Err(xcb::Error::Protocol(err)) => error!("Request failed; {err:?}"),
+                Err(xcb::Error::Connection(err)) => return Err(err),
+            }
         }
     }
 
-    pub fn poll_for_event(&self) -> Option<xcb::Event> {
+    /// Wait for the next event. Fails if the connection to the X server has been lost.
+    pub async fn wait_for_event(&self) -> Result<xcb::Event, xcb::ConnError> {
         self.connection
-            .poll_for_event()
-            .expect("Failed to poll for event")
+            .poll_until(|| self.poll_for_event().transpose())
+            .await
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.has_error().is_ok()
     }
 
     pub fn raw_connection_fd(&self) -> RawFd {
         self.connection.as_raw_fd()
     }
 
-    pub fn get_screen_resources(&self) -> randr::GetScreenResourcesCurrentReply {
+    pub async fn get_screen_resources(&self) -> randr::GetScreenResourcesCurrentReply {
         self.connection
-            .exec(&randr::GetScreenResourcesCurrent {
+            .exec_async(&randr::GetScreenResourcesCurrent {
                 window: self.root_window,
             })
+            .await
             .expect("Failed to get screen resources")
     }
 
-    /// Retrieve the crtc info for a given output.
-    pub fn get_crtc_info(&self, output: randr::Output) -> Option<randr::GetCrtcInfoReply> {
+    /// Find the XSync `IDLETIME` counter, which counts the milliseconds since the last user input.
+    /// Returns `None` if the server doesn't support it.
+    pub async fn query_idle_counter(&self) -> Option<sync::Counter> {
+        let has_sync = self
+            .connection
+            .active_extensions()
+            .any(|extension| extension == xcb::Extension::Sync);
+        if !has_sync {
+            return None;
+        }
+
+        // The extension must be initialized before any other request.
+        self.connection
+            .exec_async(&sync::Initialize {
+                desired_major_version: 3,
+                desired_minor_version: 1,
+            })
+            .await
+            .ok()?;
+        let counters = self
+            .connection
+            .exec_async(&sync::ListSystemCounters {})
+            .await
+            .ok()?;
+        counters
+            .counters()
+            .find(|counter| counter.name().as_bytes() == b"IDLETIME")
+            .map(|counter| counter.counter())
+    }
+
+    /// The current value of `counter`, `None` if it can't be queried.
+    pub async fn query_counter(&self, counter: sync::Counter) -> Option<i64> {
+        let reply = self
+            .connection
+            .exec_async(&sync::QueryCounter { counter })
+            .await
+            .ok()?;
+        let value = reply.counter_value();
+        Some((i64::from(value.hi) << 32) | i64::from(value.lo))
+    }
+
+    /// Retrieve the name and crtc region of a given output.
+    pub async fn get_output(&self, output: randr::Output) -> Option<(String, Rectangle)> {
         let config_timestamp = x::CURRENT_TIME;
         let output_info = self
             .connection
-            .exec(&randr::GetOutputInfo {
+            .exec_async(&randr::GetOutputInfo {
                 output,
                 config_timestamp,
             })
+            .await
             .expect("Failed to get output info");
 
         let crtc = output_info.crtc();
         // Require that crtcs are connected and not none.
         let valid_crtc =
             !crtc.is_none() && output_info.connection() == randr::Connection::Connected;
-        valid_crtc.then(|| {
-            self.connection
-                .exec(&randr::GetCrtcInfo {
-                    crtc,
-                    config_timestamp,
-                })
-                .expect("Failed to get crtc info")
-        })
+        if !valid_crtc {
+            return None;
+        }
+        let crtc_info = self
+            .connection
+            .exec_async(&randr::GetCrtcInfo {
+                crtc,
+                config_timestamp,
+            })
+            .await
+            .expect("Failed to get crtc info");
+        let name = String::from_utf8_lossy(output_info.name()).into_owned();
+        Some((name, Rectangle::from(&crtc_info)))
     }
 
-    pub fn query_valid_crtc_regions(&self) -> Vec<Rectangle> {
+    /// The names and regions of all connected outputs, including mirrored or contained ones.
+    pub async fn query_outputs(&self) -> Vec<(String, Rectangle)> {
         debug!("Retrieving screen resources");
-        let screen_resources = self.get_screen_resources();
+        let screen_resources = self.get_screen_resources().await;
         let outputs = screen_resources.outputs();
 
         // Get output regions.
         debug!("Retrieving regions for {} outputs", outputs.len());
-        let mut regions = Vec::new();
+        let mut named_regions = Vec::new();
         for output in outputs {
-            if let Some(crtc_info) = self.get_crtc_info(*output) {
-                regions.push(Rectangle::from(&crtc_info));
+            if let Some(named_region) = self.get_output(*output).await {
+                named_regions.push(named_region);
             }
         }
+        named_regions
+    }
 
-        // Filter and sort crtc regions.
-        let mut valid_regions = regions
-            .iter()
-            .enumerate()
-            .filter_map(|(index, rect)| {
-                regions
-                    .iter()
-                    .enumerate()
-                    .all(|(index_other, other)| index == index_other || !rect.is_inside(other))
-                    .then_some(rect.clone())
-            })
+    pub async fn query_valid_crtc_regions(&self) -> Vec<Rectangle> {
+        let regions = self
+            .query_outputs()
+            .await
+            .into_iter()
+            .map(|(_, region)| region)
             .collect::<Vec<_>>();
-        valid_regions.sort_by(compare_rectangles);
+
+        let valid_regions = filter_regions(&regions);
         debug!("Filtered valid regions:\n{valid_regions:#?}");
 
         valid_regions
     }
 
+    /// The region of the primary output, if one is set and enabled.
+    pub async fn query_primary_crtc_region(&self) -> Option<Rectangle> {
+        let output = self
+            .connection
+            .exec_async(&randr::GetOutputPrimary {
+                window: self.root_window,
+            })
+            .await
+            .expect("Failed to get primary output")
+            .output();
+        if output.is_none() {
+            return None;
+        }
+
+        let (_, region) = self.get_output(output).await?;
+        Some(region)
+    }
+
+    /// Send a request that has no reply. Returns a cookie to check it with if requests are to be
+    /// checked, otherwise errors are reported as events.
+    fn send_void<Request>(&self, request: &Request) -> Option<xcb::VoidCookieChecked>
+    where
+        Request: xcb::RequestWithoutReply,
+    {
+        if self.check_requests {
+            Some(self.connection.send_request_checked(request))
+        } else {
+            self.connection.send_request(request);
+            None
+        }
+    }
+
+    /// Send a request that has no reply. Like `pipeline_requests`, it is only waited for if
+    /// requests are checked, and failures are logged.
+    fn exec_<Request>(&self, request: &Request)
+    where
+        Request: xcb::RequestWithoutReply + std::fmt::Debug,
+    {
+        let Some(cookie) = self.send_void(request) else {
+            return;
+        };
+        if let Err(error) = self.connection.check_request(cookie) {
+            let request = format!("{request:?}");
+            error!("{}", RequestError { request, error });
+        }
+    }
+
     /// Send and await multiple void requests in parallel.
     ///
     /// Often you want to perform multiple actions one after another and retrieve their results (or non
@@ -250,23 +478,37 @@ impl Setup {
     /// nowadays with modern hardware, but all the "best practice" examples out there do this, so
     /// it can't be totally wrong, can it?
     ///
+    /// Unless requests are checked (debug builds or `SAFTBAR_CHECK_REQUESTS` set), nothing is
+    /// awaited at all and errors show up in the event queue. Otherwise all failed requests are
+    /// returned, together with the data they were built from.
+    ///
     /// TODO:
     /// Maybe redefine this function to work with anything iterator-able?
     fn pipeline_requests<T: std::fmt::Debug>(
         &self,
         data: &[T],
-        send_request: impl Fn(&T) -> xcb::VoidCookieChecked,
-    ) {
-        data.iter()
+        send_request: impl Fn(&T) -> Option<xcb::VoidCookieChecked>,
+    ) -> Result<(), Vec<RequestError>> {
+        let errors = data
+            .iter()
             .map(send_request)
             .collect::<Vec<_>>()
             .into_iter()
             .zip(data.iter())
-            .for_each(|(cookie, data)| {
-                if let Err(err) = self.connection.check_request(cookie) {
-                    panic!("Request failed: {data:?}; {err}");
-                }
-            });
+            .filter_map(|(cookie, data)| {
+                let error = self.connection.check_request(cookie?).err()?;
+                Some(RequestError {
+                    request: format!("{data:?}"),
+                    error,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 
     pub fn create_window_and_pixmap(
@@ -276,6 +518,7 @@ impl Setup {
         width: u32,
         height: u32,
         colormap: x::Colormap,
+        override_redirect: bool,
     ) -> (x::Window, x::Pixmap) {
         let window = self.connection.generate_id();
         let depth = 32; // TODO (visual == scr->root_visual) ? XCB_COPY_FROM_PARENT : 32;
@@ -283,138 +526,503 @@ impl Setup {
         let width = width.try_into().unwrap();
         let height = height.try_into().unwrap();
 
-        self.connection
-            .exec_(&x::CreateWindow {
-                depth,
-                wid: window,
-                parent: self.root_window,
-                x: x.try_into().unwrap(),
-                y: y.try_into().unwrap(),
-                width,
-                height,
-                border_width: 0,
-                class: x::WindowClass::InputOutput,
-                visual: self.visual_id,
-                value_list: &[
-                    x::Cw::BackPixel(0x0000_0000),
-                    x::Cw::BorderPixel(0x0000_0000),
-                    x::Cw::OverrideRedirect(false), // EMWH noncompliant (TODO what do i mean?)
-                    x::Cw::EventMask(x::EventMask::EXPOSURE | x::EventMask::BUTTON_PRESS),
-                    x::Cw::Colormap(colormap),
-                ],
-            })
-            .expect("Failed to create window");
+        self.exec_(&x::CreateWindow {
+            depth,
+            wid: window,
+            parent: self.root_window,
+            x: x.try_into().unwrap(),
+            y: y.try_into().unwrap(),
+            width,
+            height,
+            border_width: 0,
+            class: x::WindowClass::InputOutput,
+            visual: self.visual_id,
+            value_list: &[
+                x::Cw::BackPixel(0x0000_0000),
+                x::Cw::BorderPixel(0x0000_0000),
+                x::Cw::OverrideRedirect(override_redirect), // EMWH noncompliant (TODO what do i mean?)
+                x::Cw::EventMask(
+                    x::EventMask::EXPOSURE
+                        | x::EventMask::BUTTON_PRESS
+                        | x::EventMask::ENTER_WINDOW
+                        | x::EventMask::LEAVE_WINDOW
+                        | x::EventMask::POINTER_MOTION
+                        | x::EventMask::VISIBILITY_CHANGE,
+                ),
+                x::Cw::Colormap(colormap),
+            ],
+        });
 
         let pixmap = self.connection.generate_id();
+        self.exec_(&x::CreatePixmap {
+            depth,
+            pid: pixmap,
+            drawable: x::Drawable::Window(window),
+            width,
+            height,
+        });
+
+        (window, pixmap)
+    }
+
+    /// Create another pixmap for `window`, like the one of `create_window_and_pixmap`.
+    pub fn create_pixmap(&self, window: x::Window, width: u32, height: u32) -> x::Pixmap {
+        let pixmap = self.connection.generate_id();
+        self.exec_(&x::CreatePixmap {
+            depth: 32,
+            pid: pixmap,
+            drawable: x::Drawable::Window(window),
+            width: width.try_into().unwrap(),
+            height: height.try_into().unwrap(),
+        });
+        pixmap
+    }
+
+    pub fn free_pixmap(&self, pixmap: x::Pixmap) {
+        self.exec_(&x::FreePixmap { pixmap });
+    }
+
+    /// Create a cairo surface drawing to `pixmap`, which has the 32bit visual.
+    #[cfg(feature = "cairo")]
+    pub fn create_cairo_surface(
+        &self,
+        pixmap: x::Pixmap,
+        width: u32,
+        height: u32,
+    ) -> crate::cairo::Surface {
+        let visual = self
+            .connection
+            .get_setup()
+            .roots()
+            .flat_map(|screen| screen.allowed_depths())
+            .flat_map(|depth| depth.visuals())
+            .find(|visual| visual.visual_id() == self.visual_id)
+            .expect("Failed to find the 32bit visual");
+        let connection = self.connection.get_raw_conn().cast();
+        // The surfaces are dropped before the connection, see `X11Backend`.
+        unsafe { crate::cairo::Surface::new(connection, pixmap, visual, width, height) }
+    }
+
+    /// Whether the server supports the Present extension.
+    pub fn has_present(&self) -> bool {
         self.connection
-            .exec_(&x::CreatePixmap {
-                depth,
-                pid: pixmap,
-                drawable: x::Drawable::Window(window),
-                width,
-                height,
+            .active_extensions()
+            .any(|extension| extension == xcb::Extension::Present)
+    }
+
+    /// Report the completion of presentations to `window` as `present::CompleteNotifyEvent`.
+    pub fn select_present_events(&self, window: x::Window) {
+        self.exec_(&present::SelectInput {
+            eid: self.connection.generate_id(),
+            window,
+            event_mask: present::EventMask::COMPLETE_NOTIFY,
+        });
+    }
+
+    /// Copy pixmaps onto windows at the next vertical blank.
+    pub fn present_pixmaps(&self, presents: &[PresentPixmap]) -> Result<(), Vec<RequestError>> {
+        self.pipeline_requests(presents, |&PresentPixmap(window, pixmap, serial)| {
+            self.send_void(&present::Pixmap {
+                window,
+                pixmap,
+                serial,
+                valid: xcb::xfixes::Region::none(),
+                update: xcb::xfixes::Region::none(),
+                x_off: 0,
+                y_off: 0,
+                target_crtc: randr::Crtc::none(),
+                wait_fence: sync::Fence::none(),
+                idle_fence: sync::Fence::none(),
+                // Copying instead of flipping leaves the pixmap to us once completed.
+                options: present::Option::COPY.bits(),
+                target_msc: 0,
+                divisor: 0,
+                remainder: 0,
+                notifies: &[],
             })
-            .expect("Failed to create pixmap");
+        })
+    }
 
-        (window, pixmap)
+    pub fn destroy_window_and_pixmap(&self, window: x::Window, pixmap: x::Pixmap) {
+        self.exec_(&x::FreePixmap { pixmap });
+        self.exec_(&x::DestroyWindow { window });
     }
 
-    pub fn get_atoms<const N: usize>(&self, atom_names: &[&str; N]) -> [x::Atom; N] {
-        let conn = &self.connection;
-        atom_names
-            .map(|name| {
-                let request = x::InternAtom {
-                    only_if_exists: false,
-                    name: name.as_bytes(),
-                };
-                (name, conn.send_request(&request))
+    /// Grab the pointer, so that button presses outside of our windows are reported to `window`.
+    /// Returns whether the grab succeeded.
+    pub async fn grab_pointer(&self, window: x::Window) -> bool {
+        let reply = self
+            .connection
+            .exec_async(&x::GrabPointer {
+                owner_events: true,
+                grab_window: window,
+                event_mask: x::EventMask::BUTTON_PRESS,
+                pointer_mode: x::GrabMode::Async,
+                keyboard_mode: x::GrabMode::Async,
+                confine_to: x::Window::none(),
+                cursor: x::Cursor::none(),
+                time: x::CURRENT_TIME,
             })
-            .map(|(name, cookie)| {
-                conn.wait_for_reply(cookie)
-                    .unwrap_or_else(|err| panic!("Failed to get atom '{name}'; {err}"))
-                    .atom()
+            .await
+            .expect("Failed to grab pointer");
+        reply.status() == x::GrabStatus::Success
+    }
+
+    pub fn ungrab_pointer(&self) {
+        self.exec_(&x::UngrabPointer {
+            time: x::CURRENT_TIME,
+        });
+    }
+
+    /// Find all keycodes that produce `keysym` in any column of the keyboard mapping.
+    pub async fn keysym_to_keycodes(&self, keysym: x::Keysym) -> Vec<x::Keycode> {
+        let setup_info = self.connection.get_setup();
+        let min_keycode = setup_info.min_keycode();
+        let max_keycode = setup_info.max_keycode();
+        let mapping = self
+            .connection
+            .exec_async(&x::GetKeyboardMapping {
+                first_keycode: min_keycode,
+                count: max_keycode - min_keycode + 1,
             })
+            .await
+            .expect("Failed to get keyboard mapping");
+
+        let keysyms_per_keycode = usize::from(mapping.keysyms_per_keycode());
+        (min_keycode..=max_keycode)
+            .zip(mapping.keysyms().chunks(keysyms_per_keycode))
+            .filter_map(|(keycode, keysyms)| keysyms.contains(&keysym).then_some(keycode))
+            .collect()
     }
 
-    pub fn replace_properties(&self, window: x::Window, properties: &[ChangeProperty]) {
-        use PropertyData::{Atom, Cardinal, String};
+    /// Grab a key combination on the root window, so that it is reported to us regardless of
+    /// which window has the focus. Fails if another client has grabbed the combination already.
+    pub async fn grab_key(
+        &self,
+        keycode: x::Keycode,
+        modifiers: x::ModMask,
+    ) -> Result<(), xcb::Error> {
+        self.connection
+            .exec_async_(&x::GrabKey {
+                owner_events: true,
+                grab_window: self.root_window,
+                modifiers,
+                key: keycode,
+                pointer_mode: x::GrabMode::Async,
+                keyboard_mode: x::GrabMode::Async,
+            })
+            .await
+    }
+
+    /// Ask the window manager to add or remove `states` of the mapped `window`, as described for
+    /// `_NET_WM_STATE` by EWMH.
+    pub fn change_wm_state(
+        &self,
+        window: x::Window,
+        state_atom: x::Atom,
+        add: bool,
+        states: [x::Atom; 2],
+    ) {
+        let action = u32::from(add);
+        // Source indication 1: a normal application.
+        let data = [
+            action,
+            states[0].resource_id(),
+            states[1].resource_id(),
+            1,
+            0,
+        ];
+        let event =
+            x::ClientMessageEvent::new(window, state_atom, x::ClientMessageData::Data32(data));
+        self.exec_(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(self.root_window),
+            event_mask: x::EventMask::SUBSTRUCTURE_NOTIFY | x::EventMask::SUBSTRUCTURE_REDIRECT,
+            event: &event,
+        });
+    }
 
+    /// Move `window` to the top or bottom of the stack of its siblings.
+    pub fn restack_window(&self, window: x::Window, stack_mode: x::StackMode) {
+        self.exec_(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::StackMode(stack_mode)],
+        });
+    }
+
+    /// Create a cursor from the standard X cursor font, like `XCreateFontCursor`.
+    pub fn create_font_cursor(&self, shape: u16) -> x::Cursor {
+        let font = self.connection.generate_id();
+        self.exec_(&x::OpenFont {
+            fid: font,
+            name: b"cursor",
+        });
+
+        let cursor = self.connection.generate_id();
+        self.exec_(&x::CreateGlyphCursor {
+            cid: cursor,
+            source_font: font,
+            mask_font: font,
+            source_char: shape,
+            mask_char: shape + 1,
+            fore_red: 0,
+            fore_green: 0,
+            fore_blue: 0,
+            back_red: u16::MAX,
+            back_green: u16::MAX,
+            back_blue: u16::MAX,
+        });
+
+        self.exec_(&x::CloseFont { font });
+        cursor
+    }
+
+    pub fn free_cursor(&self, cursor: x::Cursor) {
+        self.exec_(&x::FreeCursor { cursor });
+    }
+
+    /// Set the cursor shown over `window`. `x::Cursor::none()` uses the parent's cursor.
+    pub fn set_window_cursor(&self, window: x::Window, cursor: x::Cursor) {
+        self.exec_(&x::ChangeWindowAttributes {
+            window,
+            value_list: &[x::Cw::Cursor(cursor)],
+        });
+    }
+
+    pub async fn get_atoms<const N: usize>(&self, atom_names: &[&str; N]) -> [x::Atom; N] {
         let conn = &self.connection;
+        let cookies = atom_names.map(|name| {
+            let request = x::InternAtom {
+                only_if_exists: false,
+                name: name.as_bytes(),
+            };
+            (name, conn.send_request(&request))
+        });
+
+        let mut atoms = [x::Atom::none(); N];
+        for (atom, (name, cookie)) in atoms.iter_mut().zip(cookies) {
+            *atom = conn
+                .wait_for_reply_async(cookie)
+                .await
+                .unwrap_or_else(|err| panic!("Failed to get atom '{name}'; {err}"))
+                .atom();
+        }
+        atoms
+    }
+
+    pub fn replace_properties(
+        &self,
+        window: x::Window,
+        properties: &[ChangeProperty],
+    ) -> Result<(), Vec<RequestError>> {
+        use PropertyData::{Atom, Cardinal, SizeHints, String};
+
         let mode = x::PropMode::Replace;
         self.pipeline_requests(
             properties,
             |&ChangeProperty(property, ref data)| match data {
-                Cardinal(data) => conn.send_request_checked(&x::ChangeProperty {
+                Cardinal(data) => self.send_void(&x::ChangeProperty {
                     mode,
                     window,
                     property,
                     r#type: x::ATOM_CARDINAL,
                     data,
                 }),
-                Atom(data) => conn.send_request_checked(&x::ChangeProperty {
+                Atom(data) => self.send_void(&x::ChangeProperty {
                     mode,
                     window,
                     property,
                     r#type: x::ATOM_ATOM,
                     data,
                 }),
-                String(data) => conn.send_request_checked(&x::ChangeProperty {
+                String(data) => self.send_void(&x::ChangeProperty {
                     mode,
                     window,
                     property,
                     r#type: x::ATOM_STRING,
                     data,
                 }),
+                SizeHints(data) => self.send_void(&x::ChangeProperty {
+                    mode,
+                    window,
+                    property,
+                    r#type: x::ATOM_WM_SIZE_HINTS,
+                    data: data.as_slice(),
+                }),
             },
-        );
+        )
     }
 
     /// Display windows.
-    pub fn map_windows(&self, windows: &[MapWindow]) {
+    pub fn map_windows(&self, windows: &[MapWindow]) -> Result<(), Vec<RequestError>> {
         self.pipeline_requests(windows, |&MapWindow(window)| {
-            self.connection
-                .send_request_checked(&x::MapWindow { window })
-        });
+            self.send_void(&x::MapWindow { window })
+        })
+    }
+
+    /// Hide windows.
+    pub fn unmap_windows(&self, windows: &[UnmapWindow]) -> Result<(), Vec<RequestError>> {
+        self.pipeline_requests(windows, |&UnmapWindow(window)| {
+            self.send_void(&x::UnmapWindow { window })
+        })
     }
 
     pub fn create_gc(&self, drawable: x::Drawable, value_list: &[x::Gc]) -> x::Gcontext {
         let cid = self.connection.generate_id();
-        self.connection
-            .exec_(&x::CreateGc {
-                cid,
-                drawable,
-                value_list,
-            })
-            .expect("Failed to create graphics context");
+        self.exec_(&x::CreateGc {
+            cid,
+            drawable,
+            value_list,
+        });
         cid
     }
 
+    pub fn free_gc(&self, gc: x::Gcontext) {
+        self.exec_(&x::FreeGc { gc });
+    }
+
     pub fn create_xft(&self) -> Xft {
-        Xft::new(
-            self.connection.get_raw_dpy(),
-            self.visual,
-            u64::from(self.colormap.resource_id()),
-        )
+        Xft::new(self.dpi)
     }
 
-    pub fn fill_rects(&self, rects: &[FillRect]) {
-        self.pipeline_requests(rects, |&FillRect(drawable, gc, x, y, w, h)| {
-            self.connection.send_request_checked(&x::PolyFillRectangle {
-                drawable,
-                gc,
-                rectangles: &[x::Rectangle {
-                    x: x.try_into().unwrap(),
-                    y: y.try_into().unwrap(),
-                    width: w.try_into().unwrap(),
-                    height: h.try_into().unwrap(),
-                }],
+    /// Create a render picture for drawing to `drawable`, which must have the 32bit visual.
+    pub fn create_picture(&self, drawable: x::Drawable) -> render::Picture {
+        let pid = self.connection.generate_id();
+        self.exec_(&render::CreatePicture {
+            pid,
+            drawable,
+            format: self.pict_format,
+            value_list: &[],
+        });
+        pid
+    }
+
+    pub fn free_picture(&self, picture: render::Picture) {
+        self.exec_(&render::FreePicture { picture });
+    }
+
+    /// Fill rectangles of pictures, replacing their pixels. Unlike the core protocol, render
+    /// stores the colors premultiplied by alpha, which is what compositors expect of translucent
+    /// windows.
+    pub fn fill_rects(&self, rects: &[FillRect]) -> Result<(), Vec<RequestError>> {
+        let mut batches: Vec<(render::Picture, RGBA, Vec<x::Rectangle>)> = Vec::new();
+        for &FillRect(picture, color, x, y, w, h) in rects {
+            let rectangle = x::Rectangle {
+                x: x.try_into().unwrap(),
+                y: y.try_into().unwrap(),
+                width: w.try_into().unwrap(),
+                height: h.try_into().unwrap(),
+            };
+            match batches.last_mut() {
+                Some((last_picture, last_color, rectangles))
+                    if *last_picture == picture
+                        && *last_color == color
+                        && rectangles.len() < MAX_RECTANGLES_PER_REQUEST =>
+                {
+                    rectangles.push(rectangle);
+                }
+                _ => batches.push((picture, color, vec![rectangle])),
+            }
+        }
+
+        self.pipeline_requests(&batches, |&(dst, color, ref rects)| {
+            self.send_void(&render::FillRectangles {
+                op: render::PictOp::Src,
+                dst,
+                color: render_color(color),
+                rects,
             })
+        })
+    }
+
+    /// Create a picture of infinite size filled with `color`, as the source of glyphs.
+    pub fn create_solid_fill(&self, color: RGBA) -> render::Picture {
+        let picture = self.connection.generate_id();
+        self.exec_(&render::CreateSolidFill {
+            picture,
+            color: render_color(color),
+        });
+        picture
+    }
+
+    /// Create a glyph set for glyphs of 8 bits of coverage.
+    pub fn create_glyph_set(&self) -> render::Glyphset {
+        let gsid = self.connection.generate_id();
+        self.exec_(&render::CreateGlyphSet {
+            gsid,
+            format: self.a8_format,
         });
+        gsid
     }
 
-    pub fn fill_polys(&self, polys: &[FillPoly]) {
+    pub fn free_glyph_set(&self, glyphset: render::Glyphset) {
+        self.exec_(&render::FreeGlyphSet { glyphset });
+    }
+
+    /// Upload glyphs, sending one `AddGlyphs` request per run of glyphs for the same glyph set.
+    pub fn add_glyphs(&self, glyphs: &[AddGlyph]) -> Result<(), Vec<RequestError>> {
+        // Ids, metrics and data of consecutive glyphs for the same glyph set.
+        type Batch = (render::Glyphset, Vec<u32>, Vec<render::Glyphinfo>, Vec<u8>);
+        let mut batches: Vec<Batch> = Vec::new();
+        for AddGlyph(glyph_set, id, info, data) in glyphs {
+            match batches.last_mut() {
+                Some((last_glyph_set, ids, infos, batch_data)) if last_glyph_set == glyph_set => {
+                    ids.push(*id);
+                    infos.push(*info);
+                    batch_data.extend_from_slice(data);
+                }
+                _ => batches.push((*glyph_set, vec![*id], vec![*info], data.clone())),
+            }
+        }
+
+        self.pipeline_requests(
+            &batches,
+            |&(glyphset, ref glyphids, ref glyphs, ref data)| {
+                self.send_void(&render::AddGlyphs {
+                    glyphset,
+                    glyphids,
+                    glyphs,
+                    data,
+                })
+            },
+        )
+    }
+
+    /// Composite runs of glyphs onto pictures, blending them with what has been drawn before.
+    pub fn composite_glyphs(&self, runs: &[CompositeGlyphs]) -> Result<(), Vec<RequestError>> {
+        self.pipeline_requests(
+            runs,
+            |&CompositeGlyphs(src, dst, glyphset, x, y, ref ids)| {
+                // Glyph elements hold at most 254 glyphs, all but the first continue where the
+                // previous one ended.
+                let mut glyphcmds = Vec::with_capacity(ids.len() * 4 + 8);
+                for (index, chunk) in ids.chunks(254).enumerate() {
+                    let (dx, dy) = if index == 0 { (x, y) } else { (0, 0) };
+                    glyphcmds.push(u8::try_from(chunk.len()).unwrap());
+                    glyphcmds.extend_from_slice(&[0; 3]);
+                    glyphcmds.extend_from_slice(&i16::try_from(dx).unwrap().to_ne_bytes());
+                    glyphcmds.extend_from_slice(&i16::try_from(dy).unwrap().to_ne_bytes());
+                    for id in chunk {
+                        glyphcmds.extend_from_slice(&id.to_ne_bytes());
+                    }
+                }
+                self.send_void(&render::CompositeGlyphs32 {
+                    op: render::PictOp::Over,
+                    src,
+                    dst,
+                    mask_format: self.a8_format,
+                    glyphset,
+                    src_x: 0,
+                    src_y: 0,
+                    glyphcmds: &glyphcmds,
+                })
+            },
+        )
+    }
+
+    pub fn fill_polys(&self, polys: &[FillPoly]) -> Result<(), Vec<RequestError>> {
         self.pipeline_requests(polys, |&FillPoly(drawable, gc, ref points)| {
-            self.connection.send_request_checked(&x::FillPoly {
+            self.send_void(&x::FillPoly {
                 drawable,
                 gc,
                 shape: x::PolyShape::Convex,
@@ -427,14 +1035,14 @@ impl Setup {
                     })
                     .collect::<Vec<_>>(),
             })
-        });
+        })
     }
 
-    pub fn copy_areas(&self, areas: &[CopyArea]) {
-        self.pipeline_requests(areas, |&CopyArea(pixmap, window, gc, w, h)| {
-            self.connection.send_request_checked(&x::CopyArea {
+    pub fn copy_areas(&self, areas: &[CopyArea]) -> Result<(), Vec<RequestError>> {
+        self.pipeline_requests(areas, |&CopyArea(pixmap, dst_drawable, gc, w, h)| {
+            self.send_void(&x::CopyArea {
                 src_drawable: x::Drawable::Pixmap(pixmap),
-                dst_drawable: x::Drawable::Window(window),
+                dst_drawable,
                 gc,
                 src_x: 0,
                 src_y: 0,
@@ -443,7 +1051,36 @@ impl Setup {
                 width: w.try_into().unwrap(),
                 height: h.try_into().unwrap(),
             })
+        })
+    }
+
+    /// The pixel values of the 32 bit `drawable`, row by row. `None` if they can't be read.
+    pub async fn get_image(&self, drawable: x::Drawable, w: u32, h: u32) -> Option<Vec<u32>> {
+        let reply = self
+            .connection
+            .exec_async(&x::GetImage {
+                format: x::ImageFormat::ZPixmap,
+                drawable,
+                x: 0,
+                y: 0,
+                width: u16::try_from(w).ok()?,
+                height: u16::try_from(h).ok()?,
+                plane_mask: u32::MAX,
+            })
+            .await
+            .map_err(|err| error!("Failed to get image; {err}"))
+            .ok()?;
+
+        let msb_first = self.connection.get_setup().image_byte_order() == x::ImageOrder::MsbFirst;
+        let pixels = reply.data().chunks_exact(4).map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if msb_first {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
         });
+        Some(pixels.collect())
     }
 
     pub fn flush(&self) {
@@ -452,3 +1089,99 @@ impl Setup {
             .expect("Failed to flush xcb connection");
     }
 }
+
+/// A render color, scaled to 16 bits and premultiplied by alpha.
+fn render_color((r, g, b, a): RGBA) -> render::Color {
+    let channel = |value: u8| (u32::from(value) * u32::from(a) * 257 / 255) as u16;
+    render::Color {
+        red: channel(r),
+        green: channel(g),
+        blue: channel(b),
+        alpha: u16::from(a) * 257,
+    }
+}
+
+/// The value of `Xft.dpi` in an X resource database, e.g. the `RESOURCE_MANAGER` property.
+fn parse_xft_dpi(resources: &str) -> Option<f64> {
+    resources.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (name.trim() == "Xft.dpi")
+            .then(|| value.trim().parse().ok())
+            .flatten()
+            .filter(|dpi: &f64| *dpi > 0.0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, w: u32, h: u32) -> Rectangle {
+        Rectangle { x, y, w, h }
+    }
+
+    #[test]
+    fn mirrored_outputs_get_one_bar() {
+        let mirrored = rect(0, 0, 1920, 1080);
+        let regions = filter_regions(&[mirrored.clone(), mirrored.clone()]);
+        assert_eq!(regions, vec![mirrored]);
+    }
+
+    #[test]
+    fn mirrored_outputs_next_to_another_output() {
+        let left = rect(0, 0, 1920, 1080);
+        let right = rect(1920, 0, 2560, 1440);
+        let regions = filter_regions(&[right.clone(), left.clone(), right.clone()]);
+        assert_eq!(regions, vec![left, right]);
+    }
+
+    #[test]
+    fn contained_outputs_are_dropped() {
+        let large = rect(0, 0, 2560, 1440);
+        let regions = filter_regions(&[rect(0, 0, 1920, 1080), large.clone()]);
+        assert_eq!(regions, vec![large]);
+    }
+
+    #[test]
+    fn rectangle_order() {
+        let rects = (0..3)
+            .flat_map(|x| (0..3).map(move |y| (x, y)))
+            .flat_map(|(x, y)| (1..4).flat_map(move |w| (1..4).map(move |h| rect(x, y, w, h))))
+            .collect::<Vec<_>>();
+        for a in &rects {
+            assert_eq!(compare_rectangles(a, &a.clone()), Ordering::Equal);
+            for b in &rects {
+                let order = compare_rectangles(a, b);
+                assert_eq!(compare_rectangles(b, a), order.reverse());
+                assert_eq!(order == Ordering::Equal, a == b);
+                if a.x != b.x {
+                    assert_eq!(order, a.x.cmp(&b.x), "{a:?} {b:?}");
+                } else if a.y != b.y {
+                    assert_eq!(order, a.y.cmp(&b.y), "{a:?} {b:?}");
+                }
+                for c in &rects {
+                    if order.is_le() && compare_rectangles(b, c).is_le() {
+                        assert!(compare_rectangles(a, c).is_le(), "{a:?} {b:?} {c:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_and_stacked_outputs() {
+        let top = rect(0, 0, 1920, 1080);
+        let bottom = rect(0, 1080, 1920, 1080);
+        let overlapping = rect(1000, 500, 1920, 1080);
+        let regions = filter_regions(&[overlapping.clone(), bottom.clone(), top.clone()]);
+        assert_eq!(regions, vec![top, bottom, overlapping]);
+    }
+
+    #[test]
+    fn xft_dpi_resource() {
+        let resources = "Xcursor.size:\t24\nXft.antialias:\t1\nXft.dpi:\t144\n";
+        assert_eq!(parse_xft_dpi(resources), Some(144.0));
+        assert_eq!(parse_xft_dpi("Xft.dpi: none\n"), None);
+        assert_eq!(parse_xft_dpi(""), None);
+    }
+}
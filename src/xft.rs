@@ -1,43 +1,42 @@
-use std::ops::DerefMut;
+//! Text rendering the way Xft does it, without going through Xlib: fonts are picked by
+//! fontconfig, rasterized by FreeType and drawn from glyph sets of the Render extension.
 
-use x11::{xft, xlib, xrender};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-pub type RGBA = (u8, u8, u8, u8);
-
-/// Smart object for serverside allocated `XftColor`s.
-pub struct Color {
-    /// Xft color object. Used as a pointer, therefore the object itself is never accessed.
-    #[allow(dead_code)]
-    color: Box<xft::XftColor>,
+use log::debug;
+use xcb::render;
 
-    /// Pointer to the color object. This is a cached value for convenience.
-    color_ptr: *mut xft::XftColor,
-
-    /// Render reference resources.
-    display: *mut xlib::Display,
-    visual: *mut xlib::Visual,
-    colormap_id: u64,
-}
+use crate::fontconfig;
+use crate::freetype::{Face, Glyph, Library, Synthesis};
+use crate::setup::{AddGlyph, CompositeGlyphs, RequestError, Setup};
 
-impl Drop for Color {
-    fn drop(&mut self) {
-        unsafe { xft::XftColorFree(self.display, self.visual, self.colormap_id, self.color_ptr) };
-    }
-}
+pub type RGBA = (u8, u8, u8, u8);
 
-/// Smart object for `XftFont` pointers.
-#[derive(Debug)]
+/// A font at a fixed size, with the glyphs rasterized so far.
 pub struct Font {
-    font: *mut xft::XftFont,
+    face: Face,
+    file: String,
+    pixel_size: f64,
     ascent: u32,
-    #[allow(dead_code)]
     descent: u32,
-    display: *mut xlib::Display,
+    /// Rasterized glyphs by glyph index.
+    glyphs: RefCell<HashMap<u32, Glyph>>,
+    /// The server side copies of the glyphs, created when drawing for the first time. Freed by
+    /// `Xft::close_font`.
+    glyph_set: Cell<Option<render::Glyphset>>,
+    uploaded: RefCell<HashSet<u32>>,
 }
 
-impl Drop for Font {
-    fn drop(&mut self) {
-        unsafe { xft::XftFontClose(self.display, self.font) };
+impl std::fmt::Debug for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Font")
+            .field("file", &self.file)
+            .field("pixel_size", &self.pixel_size)
+            .field("ascent", &self.ascent)
+            .field("descent", &self.descent)
+            .finish_non_exhaustive()
     }
 }
 
@@ -46,87 +45,60 @@ impl Font {
     pub fn asc_and_desc(&self) -> u32 {
         self.ascent + self.descent
     }
-}
 
-/// Smart object for `XftDraw` pointers.
-pub struct Draw {
-    draw: *mut xft::XftDraw,
-}
+    #[must_use]
+    pub fn ascent(&self) -> u32 {
+        self.ascent
+    }
+
+    /// Call `f` with the index and glyph of each character of `text`, rasterizing glyphs on first
+    /// use. Glyphs that fail to rasterize are drawn as nothing.
+    fn for_each_glyph(&self, text: &str, mut f: impl FnMut(u32, &Glyph)) {
+        let mut glyphs = self.glyphs.borrow_mut();
+        for c in text.chars() {
+            let index = self.face.glyph_index(c);
+            let glyph = glyphs
+                .entry(index)
+                .or_insert_with(|| self.face.render(index).unwrap_or_default());
+            f(index, glyph);
+        }
+    }
 
-impl Drop for Draw {
-    fn drop(&mut self) {
-        unsafe { xft::XftDrawDestroy(self.draw) };
+    /// How far the ink of `text` extends above and below the baseline.
+    fn ink_extents(&self, text: &str) -> (i32, i32) {
+        let (mut above, mut below) = (0, 0);
+        self.for_each_glyph(text, |_, glyph| {
+            if glyph.height > 0 {
+                let height = i32::try_from(glyph.height).unwrap_or(i32::MAX);
+                above = above.max(glyph.top);
+                below = below.max(height - glyph.top);
+            }
+        });
+        (above, below)
     }
 }
 
 /// State machine holding the resources for rendering text.
 pub struct Xft {
-    display: *mut xlib::Display,
-    visual: *mut xlib::Visual,
-    colormap_id: u64,
+    library: Rc<Library>,
+    /// The resolution that font sizes in points are converted to pixels with.
+    dpi: f64,
 }
 
 impl Xft {
-    pub fn new(display: *mut xlib::Display, visual: *mut xlib::Visual, colormap_id: u64) -> Self {
-        Self {
-            display,
-            visual,
-            colormap_id,
-        }
-    }
-
-    /// Create a color object, wrap it into a smart object and store.
-    ///
     /// # Panics
     ///
-    /// This function expects `XftColorAllocValue` to not fail.
+    /// If FreeType can't be initialized.
     #[must_use]
-    pub fn create_color(&self, rgba: RGBA) -> Color {
-        let mut render_color = xrender::XRenderColor {
-            red: u16::from(rgba.0) << 8,
-            green: u16::from(rgba.1) << 8,
-            blue: u16::from(rgba.2) << 8,
-            alpha: u16::from(rgba.3) << 8,
-        };
-
-        let mut color = xft::XftColor {
-            pixel: 0,
-            color: xrender::XRenderColor {
-                red: 0,
-                green: 0,
-                blue: 0,
-                alpha: 0,
-            },
-        };
-
-        let display = self.display;
-        let visual = self.visual;
-        let colormap_id = self.colormap_id;
-
-        let result = unsafe {
-            xft::XftColorAllocValue(
-                display,
-                visual,
-                colormap_id,
-                std::ptr::addr_of_mut!(render_color),
-                std::ptr::addr_of_mut!(color),
-            )
-        };
-        assert_ne!(result, 0, "Xft color creation failed");
-
-        let mut color = Box::new(color);
-        let color_ptr = std::ptr::addr_of_mut!(*color.deref_mut());
-        Color {
-            color,
-            color_ptr,
-            display,
-            visual,
-            colormap_id,
+    pub fn new(dpi: f64) -> Self {
+        Self {
+            library: Library::new().expect("Failed to initialize FreeType"),
+            dpi,
         }
     }
 
-    /// Load a font by pattern, wrap it into a smart object and store.
-    /// Trailing 0 required for `font_pattern`!
+    /// Load a font by fontconfig pattern, e.g. a family with optional properties like
+    /// `Ubuntu Mono:weight=bold`.
     ///
     /// # Sizes
     ///
@@ -136,109 +108,206 @@ impl Xft {
     /// pixel height, which will most likely be one pixel more than the specified height.
     ///
     /// Try out different fractional heights until you find a symmetric one for the font that you
-    /// want to use.
+    /// want to use, or let `create_font_for_height` do that.
     ///
     /// # Panics
     ///
-    /// This function expects `XftFontLoad` to not fail and the loaded font to have sensible
-    /// values, especially positive ascent and descent.
-    pub fn create_font(&mut self, font_family: &str, size: f32) -> Font {
-        let display = self.display;
-        let font_pattern = format!(
-            "{font_family}:size={size:.5}:antialias=true:hinting=true:hintstyle=hintnone\0"
-        );
-        let pattern_ptr = font_pattern.as_ptr().cast::<i8>();
-        let font = unsafe { xft::XftFontOpenName(display, 0, pattern_ptr) };
-        assert!(!font.is_null(), "Xft font creation failed");
-        let x_font = &unsafe { *font };
-        let (ascent, descent) = (
-            x_font.ascent.try_into().expect("Font ascent is negative"),
-            x_font.descent.try_into().expect("Font descent is negative"),
-        );
+    /// This function expects fontconfig to find a font and FreeType to load it.
+    #[must_use]
+    pub fn create_font(&self, font_family: &str, size: f32) -> Font {
+        let pattern = format!("{font_family}:size={size:.5}:dpi={:.5}", self.dpi);
+        let font_match = fontconfig::match_font(&pattern).expect("No font found");
+        let synthesis = Synthesis {
+            embolden: font_match.embolden,
+            oblique: font_match.oblique,
+        };
+        let face = Face::new(
+            &self.library,
+            &font_match.file,
+            font_match.index,
+            font_match.pixel_size,
+            synthesis,
+        )
+        .expect("Font loading failed");
 
         Font {
-            font,
-            ascent,
-            descent,
-            display,
+            ascent: face.ascent(),
+            descent: face.descent(),
+            face,
+            file: font_match.file.to_string_lossy().into_owned(),
+            pixel_size: font_match.pixel_size,
+            glyphs: RefCell::new(HashMap::new()),
+            glyph_set: Cell::new(None),
+            uploaded: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Free the glyph set of `font`, if it has been drawn. The font must not be drawn anymore.
+    pub fn close_font(&self, setup: &Setup, font: &Font) {
+        if let Some(glyph_set) = font.glyph_set.get() {
+            setup.free_glyph_set(glyph_set);
         }
     }
 
-    /// Create a `Draw` - a temporary object holding references to the drawable and the context.
-    ///
-    /// # Panics
-    ///
-    /// This function expects `XftDrawCreate` to not fail.
     #[must_use]
-    pub fn new_draw(&self, pixmap_id: u64) -> Draw {
-        let draw =
-            unsafe { xft::XftDrawCreate(self.display, pixmap_id, self.visual, self.colormap_id) };
-        assert!(!draw.is_null(), "Failed to create Xft draw");
+    pub fn cursor_offset(&self, text: &str, font: &Font) -> u32 {
+        let mut offset = 0;
+        font.for_each_glyph(text, |_, glyph| offset += glyph.advance);
+        offset
+            .try_into()
+            .expect("Cursor offset is (probably) a negative value")
+    }
 
-        Draw { draw }
+    /// The height of lowercase letters without ascenders, measured on `x`.
+    #[must_use]
+    pub fn x_height(&self, font: &Font) -> u32 {
+        let (above, below) = font.ink_extents("x");
+        u32::try_from(above + below).unwrap_or(0)
     }
 
-    fn c_text_ptr_len(text: &str) -> (*const u8, i32) {
-        (
-            text.as_ptr(),
-            text.len().try_into().expect("Text is longer than i16::MAX"),
-        )
+    /// The difference in pixels between the space above and below the glyphs of `Hg`, i.e. how
+    /// far off center text is drawn within a line of `font`.
+    fn ink_asymmetry(&self, font: &Font) -> u32 {
+        let (ink_above, ink_below) = font.ink_extents("Hg");
+        let space_above = i32::try_from(font.ascent).unwrap_or(i32::MAX) - ink_above;
+        let space_below = i32::try_from(font.descent).unwrap_or(i32::MAX) - ink_below;
+        space_above.abs_diff(space_below)
     }
 
+    /// Load `font_family` at the fractional size that fits a line of `height` pixels best.
+    ///
+    /// Sizes are probed in steps of a quarter point. Sizes whose line is higher than `height` are
+    /// skipped. Among the others, the highest line wins, and among equally high lines the one
+    /// whose glyphs are closest to the vertical center. If even the smallest probed size is too
+    /// high, that one is used.
+    ///
+    /// # Panics
+    ///
+    /// Like `create_font`.
     #[must_use]
-    pub fn cursor_offset(&self, text: &str, font: &Font) -> u32 {
-        let (text_ptr, text_len) = Self::c_text_ptr_len(text);
-        let mut extents = xrender::XGlyphInfo {
-            width: 0,
-            height: 0,
-            x: 0,
-            y: 0,
-            xOff: 0,
-            yOff: 0,
-        };
-        unsafe {
-            let extents_ptr = std::ptr::addr_of_mut!(extents);
-            xft::XftTextExtentsUtf8(self.display, font.font, text_ptr, text_len, extents_ptr);
-            extents
-                .xOff
-                .try_into()
-                .expect("Cursor offset is (probably) a negative value")
+    pub fn create_font_for_height(&self, font_family: &str, height: u32) -> Font {
+        // Sizes are in points, lines are usually 1.2 to 2 times as high in pixels.
+        let min_quarters = (height * 4 * 2 / 5).max(1);
+        let max_quarters = (height * 4).max(min_quarters);
+
+        let mut best: Option<((u32, u32), Font)> = None;
+        for quarters in min_quarters..=max_quarters {
+            let font = self.create_font(font_family, quarters as f32 / 4.0);
+            let line_height = font.asc_and_desc();
+            if line_height > height {
+                // Lines only get higher from here on.
+                if best.is_none() {
+                    best = Some(((0, 0), font));
+                }
+                break;
+            }
+
+            let key = (height - line_height, self.ink_asymmetry(&font));
+            let better = match &best {
+                Some((best_key, _)) => key < *best_key,
+                None => true,
+            };
+            if better {
+                best = Some((key, font));
+            }
         }
+
+        let (_, font) = best.expect("At least one font size is probed");
+        debug!("Picked font size for a height of {height}: {font:?}");
+        font
     }
 
+    /// Draw `text` in the color of the solid fill picture `fill` onto `picture`, uploading the
+    /// glyphs that haven't been drawn with `font` before.
+    ///
+    /// # Errors
+    ///
+    /// Returns the requests that the X server rejected, if requests are checked.
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_string(
         &self,
+        setup: &Setup,
         text: &str,
-        draw: &Draw,
-        color: &Color,
+        picture: render::Picture,
+        fill: render::Picture,
         font: &Font,
+        canvas_top: u32,
         canvas_height: u32,
         cursor_offset: u32,
-    ) {
-        let (text_ptr, text_len) = Self::c_text_ptr_len(text);
-        // WTF... if was right here all the time. If my canvas has the same size as the font then i
-        // don't need no centering stuff, the ascent IS the baseline offset.
-        // The major problem is choosing a font size so that its (!) rasterization is vertically
-        // symmetric! See screenshot for reference.
-        // let baseline_offset = font.ascent;
+    ) -> Result<(), Vec<RequestError>> {
+        let glyph_set = font.glyph_set.get().unwrap_or_else(|| {
+            let glyph_set = setup.create_glyph_set();
+            font.glyph_set.set(Some(glyph_set));
+            glyph_set
+        });
+
+        let mut ids = Vec::new();
+        let mut uploads = Vec::new();
+        let mut uploaded = font.uploaded.borrow_mut();
+        font.for_each_glyph(text, |index, glyph| {
+            ids.push(index);
+            if uploaded.insert(index) {
+                uploads.push(AddGlyph(
+                    glyph_set,
+                    index,
+                    glyph_info(glyph),
+                    padded_rows(glyph),
+                ));
+            }
+        });
 
         // If the canvas is larger than asc+desc then we hope that the overhang is an even number
-        // of pixels. Otherwise we're off by 0.5 pixels.
-        let baseline_offset = (canvas_height - font.asc_and_desc()) / 2 + font.ascent;
-        unsafe {
-            xft::XftDrawStringUtf8(
-                draw.draw,
-                color.color_ptr,
-                font.font,
-                cursor_offset
-                    .try_into()
-                    .expect("Cursor offset not representable as c_int"),
-                baseline_offset
-                    .try_into()
-                    .expect("Baseline offset not representable as c_int"),
-                text_ptr,
-                text_len,
-            );
+        // of pixels. Otherwise we're off by 0.5 pixels. `Bar` only passes canvases of exactly
+        // asc+desc and places the text itself, as chosen by `OddOverhang`.
+        let overhang = canvas_height.saturating_sub(font.asc_and_desc());
+        let baseline_offset = canvas_top + overhang / 2 + font.ascent;
+        let run = CompositeGlyphs(
+            fill,
+            picture,
+            glyph_set,
+            cursor_offset
+                .try_into()
+                .expect("Cursor offset not representable as i32"),
+            baseline_offset
+                .try_into()
+                .expect("Baseline offset not representable as i32"),
+            ids,
+        );
+
+        let uploaded = setup.add_glyphs(&uploads);
+        let drawn = setup.composite_glyphs(&[run]);
+        match (uploaded, drawn) {
+            (Ok(()), Ok(())) => Ok(()),
+            (uploaded, drawn) => Err([uploaded.err(), drawn.err()]
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect()),
         }
     }
 }
+
+/// The metrics of `glyph` for a glyph set, relative to the pen position on the baseline.
+fn glyph_info(glyph: &Glyph) -> render::Glyphinfo {
+    let to_i16 = |value: i32| i16::try_from(value).unwrap_or(0);
+    render::Glyphinfo {
+        width: u16::try_from(glyph.width).unwrap_or(0),
+        height: u16::try_from(glyph.height).unwrap_or(0),
+        x: to_i16(-glyph.left),
+        y: to_i16(glyph.top),
+        x_off: to_i16(glyph.advance),
+        y_off: 0,
+    }
+}
+
+/// The coverage of `glyph` with each row padded to 4 bytes, as glyph sets expect.
+fn padded_rows(glyph: &Glyph) -> Vec<u8> {
+    let width = glyph.width as usize;
+    let stride = (width + 3) & !3;
+    let mut data = Vec::with_capacity(stride * glyph.height as usize);
+    for row in glyph.pixels.chunks_exact(width.max(1)) {
+        data.extend_from_slice(row);
+        data.resize(data.len() + stride - width, 0);
+    }
+    data
+}
@@ -0,0 +1,437 @@
+//! Compare rendered bars against the reference images in `tests/golden`.
+//!
+//! The separator geometry is easy to get off by a pixel, so every powerline and octagon variant
+//! is rendered with the headless backend and compared pixel by pixel. After an intentional change
+//! to the rendering, regenerate the references with
+//!
+//! ```sh
+//! SAFTBAR_BLESS=1 cargo test --features headless --test golden
+//! ```
+//!
+//! and review the updated images before committing them.
+//!
+//! The X11 backend is compared against the headless one on Xvfb, see `xvfb::x11_separators`, which is
+//! ignored by default. Run it with
+//!
+//! ```sh
+//! cargo test --features headless --test golden -- --ignored
+//! ```
+//!
+//! It hasn't passed on Xvfb yet, so a failure there points at the test as much as at the backend.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use saftbar::backend::headless::{HeadlessBackend, Image};
+use saftbar::backend::Rectangle;
+use saftbar::bar::{
+    Alignment, Bar, BarOptions, ContentItem, ContentShape, Geometry, ItemLocation, PopupPlacement,
+    Position, PowerlineDirection, PowerlineFill, PowerlineStyle,
+};
+use saftbar::xft::RGBA;
+
+const FONT: &[u8] = include_bytes!("golden/DejaVuSansMono.ttf");
+const WIDTH: u32 = 96;
+
+const RED: RGBA = (255, 0, 0, 255);
+const BLUE: RGBA = (0, 0, 255, 255);
+const WHITE: RGBA = (255, 255, 255, 255);
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn text(text: &str, bg: RGBA) -> ContentItem {
+    ContentItem {
+        fg: WHITE,
+        bg,
+        shape: ContentShape::Text(text.to_owned()),
+        ..Default::default()
+    }
+}
+
+async fn render(items: &[ContentItem]) -> Image {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 0,
+        y: 0,
+        w: WIDTH,
+        h: 0,
+    };
+    let mut bar = Bar::with_backend(backend, &[region]);
+    bar.clear_monitors();
+    bar.draw(0, Alignment::Left, items);
+    bar.screenshot(0).await
+}
+
+fn read_png(path: &PathBuf) -> Image {
+    let file = File::open(path).unwrap_or_else(|err| panic!("Failed to open {path:?}: {err}"));
+    let mut reader = png::Decoder::new(file).read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    assert_eq!(info.bit_depth, png::BitDepth::Eight);
+
+    Image {
+        w: info.width,
+        h: info.height,
+        pixels: data[..info.buffer_size()]
+            .chunks_exact(4)
+            .map(|p| (p[0], p[1], p[2], p[3]))
+            .collect(),
+    }
+}
+
+fn write_png(path: &PathBuf, image: &Image) {
+    let file = File::create(path).unwrap_or_else(|err| panic!("Failed to create {path:?}: {err}"));
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.w, image.h);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data = image
+        .pixels
+        .iter()
+        .flat_map(|&(r, g, b, a)| [r, g, b, a])
+        .collect::<Vec<_>>();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .unwrap();
+}
+
+fn assert_golden(name: &str, image: &Image) {
+    let path = golden_path(name);
+    if std::env::var_os("SAFTBAR_BLESS").is_some() {
+        write_png(&path, image);
+        return;
+    }
+
+    assert_same_image(name, image, &read_png(&path));
+}
+
+fn assert_same_image(name: &str, image: &Image, expected: &Image) {
+    let differing = image
+        .pixels
+        .iter()
+        .zip(&expected.pixels)
+        .filter(|(a, b)| a != b)
+        .count();
+    if (image.w, image.h) != (expected.w, expected.h) || differing > 0 {
+        let actual_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.png"));
+        write_png(&actual_path, image);
+        panic!(
+            "{name}: rendered {}x{} with {differing} differing pixels, expected {}x{}; \
+             see {actual_path:?}",
+            image.w, image.h, expected.w, expected.h
+        );
+    }
+}
+
+#[tokio::test]
+async fn text_items() {
+    let image = render(&[text(" ab ", RED), text(" cd ", BLUE)]).await;
+    assert_golden("text", &image);
+}
+
+#[tokio::test]
+async fn set_content_repaints_changes() {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 0,
+        y: 0,
+        w: WIDTH,
+        h: 0,
+    };
+    let mut bar = Bar::with_backend(backend, &[region]);
+    bar.clear_monitors();
+    bar.set_content(0, Alignment::Left, vec![text(" abcdef ", BLUE)]);
+    bar.set_content(
+        0,
+        Alignment::Left,
+        vec![text(" ab ", RED), text(" cd ", BLUE)],
+    );
+
+    // The stale part of the wider item is cleared, as if drawn from scratch.
+    assert_golden("text", &bar.screenshot(0).await);
+}
+
+#[test]
+fn bottom_bar_geometry() {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 1920,
+        y: 0,
+        w: 2560,
+        h: 1440,
+    };
+    let options = BarOptions {
+        position: Position::Bottom,
+        geometry: Geometry {
+            x: 100,
+            y: 10,
+            w: Some(800),
+            h: None,
+        },
+        ..BarOptions::default()
+    };
+    let bar = Bar::with_backend_options(backend, &[region], options);
+
+    let expected = Rectangle {
+        x: 2020,
+        y: 1440 - 10 - bar.height(),
+        w: 800,
+        h: bar.height(),
+    };
+    assert_eq!(bar.monitor_geometry(0), expected);
+}
+
+#[tokio::test]
+async fn hover_colors() {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 0,
+        y: 0,
+        w: WIDTH,
+        h: 0,
+    };
+    let mut bar = Bar::with_backend(backend, &[region]);
+    bar.clear_monitors();
+    bar.draw(
+        0,
+        Alignment::Left,
+        &[text(" ab ", RED).hover_fg(BLUE).hover_bg(WHITE)],
+    );
+
+    let location = ItemLocation {
+        monitor: 0,
+        alignment: Alignment::Left,
+        index: 0,
+    };
+    bar.set_hovered(Some(location));
+    let hovered = render(&[text(" ab ", WHITE).fg(BLUE)]).await;
+    assert_same_image("hovered", &bar.screenshot(0).await, &hovered);
+
+    bar.set_hovered(None);
+    let unhovered = render(&[text(" ab ", RED)]).await;
+    assert_same_image("unhovered", &bar.screenshot(0).await, &unhovered);
+}
+
+#[test]
+fn popup_anchoring() {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 100,
+        y: 300,
+        w: WIDTH,
+        h: 0,
+    };
+    let mut bar = Bar::with_backend(backend, &[region]);
+    let items = [text(" ab ", RED), text(" cd ", BLUE)];
+    bar.draw(0, Alignment::Left, &items);
+    let anchor_x = bar.explain_layout(0, Alignment::Left, &items)[1].x;
+    let anchor = ItemLocation {
+        monitor: 0,
+        alignment: Alignment::Left,
+        index: 1,
+    };
+    let height = bar.height();
+
+    // Below the item, one line per row.
+    bar.open_popup(
+        anchor,
+        PopupPlacement::Below,
+        &[vec![ContentItem::from("x")]],
+    );
+    assert_eq!(bar.popup_anchor(), Some(anchor));
+    let popup = bar.popup_geometry().unwrap();
+    assert_eq!(
+        (popup.x, popup.y, popup.h),
+        (100 + anchor_x, 300 + height, height)
+    );
+
+    // Above the bar, ending where it starts.
+    let row = vec![ContentItem::from("x")];
+    bar.open_popup(anchor, PopupPlacement::Above, &[row.clone(), row]);
+    let popup = bar.popup_geometry().unwrap();
+    assert_eq!(
+        (popup.x, popup.y, popup.h),
+        (100 + anchor_x, 300 - 2 * height, 2 * height)
+    );
+
+    // Wide popups are kept within the monitor.
+    let row = vec![ContentItem::from("a rather wide popup")];
+    bar.open_popup(anchor, PopupPlacement::Below, &[row]);
+    let popup = bar.popup_geometry().unwrap();
+    assert!(popup.w > WIDTH - anchor_x);
+    assert_eq!(popup.x, (100 + WIDTH).saturating_sub(popup.w));
+
+    bar.close_popup();
+    assert_eq!(bar.popup_geometry(), None);
+}
+
+#[tokio::test]
+async fn borders() {
+    let backend = HeadlessBackend::new(FONT, 16.0).expect("Failed to load the test font");
+    let region = Rectangle {
+        x: 0,
+        y: 0,
+        w: WIDTH,
+        h: 0,
+    };
+    let mut bar = Bar::with_backend(backend, &[region]);
+    let items = [text(" ab ", RED).border(WHITE, 2)];
+    bar.clear_monitors();
+    bar.draw(0, Alignment::Left, &items);
+    let image = bar.screenshot(0).await;
+    let pixel = |x: u32, y: u32| image.pixels[(y * image.w + x) as usize];
+    let right = bar.explain_layout(0, Alignment::Left, &items)[0].w - 1;
+    let bottom = image.h - 1;
+
+    // The text starts with a space, so the background shows next to the border.
+    for (x, y) in [
+        (0, 0),
+        (1, 1),
+        (0, bottom),
+        (1, 9),
+        (right, 0),
+        (right - 1, bottom),
+    ] {
+        assert_eq!(pixel(x, y), WHITE, "at {x}, {y}");
+    }
+    for (x, y) in [(2, 2), (2, 9), (2, bottom - 2)] {
+        assert_eq!(pixel(x, y), RED, "at {x}, {y}");
+    }
+}
+
+#[tokio::test]
+async fn letter_spacing() {
+    let spaced = render(&[text("ab", RED).letter_spacing(3)]).await;
+    let apart = render(&[text("a", RED), text("", RED).padding(3, 0), text("b", RED)]).await;
+    assert_same_image("letter_spacing", &spaced, &apart);
+}
+
+#[tokio::test]
+async fn separators() {
+    for (style, style_name) in [
+        (PowerlineStyle::Powerline, "powerline"),
+        (PowerlineStyle::Octagon, "octagon"),
+    ] {
+        for (fill, fill_name) in [(PowerlineFill::Full, "full"), (PowerlineFill::No, "no")] {
+            for (direction, direction_name) in [
+                (PowerlineDirection::Left, "left"),
+                (PowerlineDirection::Right, "right"),
+            ] {
+                let separator = ContentItem {
+                    fg: RED,
+                    bg: BLUE,
+                    shape: ContentShape::Powerline(style, fill, direction),
+                    ..Default::default()
+                };
+                let image = render(&[text(" a ", RED), separator, text(" b ", BLUE)]).await;
+                assert_golden(
+                    &format!("{style_name}_{fill_name}_{direction_name}"),
+                    &image,
+                );
+            }
+        }
+    }
+}
+
+// With the `cairo` feature separators are anti-aliased, so they can't match.
+#[cfg(not(feature = "cairo"))]
+mod xvfb {
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// An Xvfb server on a display of its own, which `DISPLAY` points to. Killed when dropped.
+    struct Xvfb(Child);
+
+    impl Xvfb {
+        fn start() -> Self {
+            let display = (90..200)
+                .find(|n| {
+                    !Path::new(&format!("/tmp/.X11-unix/X{n}")).exists()
+                        && !Path::new(&format!("/tmp/.X{n}-lock")).exists()
+                })
+                .expect("No free display");
+            let child = Command::new("Xvfb")
+                .args([
+                    &format!(":{display}"),
+                    "-screen",
+                    "0",
+                    "320x240x24",
+                    "-nolisten",
+                    "tcp",
+                ])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|err| panic!("Failed to start Xvfb: {err}"));
+            let xvfb = Self(child);
+
+            let socket = format!("/tmp/.X11-unix/X{display}");
+            let started = Instant::now();
+            while !Path::new(&socket).exists() {
+                assert!(
+                    started.elapsed() < Duration::from_secs(10),
+                    "Xvfb didn't start"
+                );
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            std::env::set_var("DISPLAY", format!(":{display}"));
+            xvfb
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// The X11 backend draws separators with `FillPoly`, and the bars it reads back with
+    /// `GetImage` must match the headless backend, whose output the references pin. Items are
+    /// padded instead of holding text, since text depends on the fonts installed on the machine.
+    #[tokio::test]
+    #[ignore = "needs Xvfb"]
+    async fn x11_separators() {
+        let _xvfb = Xvfb::start();
+        let height = render(&[]).await.h;
+        let options = BarOptions {
+            geometry: Geometry {
+                w: Some(WIDTH),
+                h: Some(height),
+                ..Geometry::default()
+            },
+            ..BarOptions::default()
+        };
+        let mut bar = Bar::with_options(options).await;
+
+        for style in [PowerlineStyle::Powerline, PowerlineStyle::Octagon] {
+            for fill in [PowerlineFill::Full, PowerlineFill::No] {
+                for direction in [PowerlineDirection::Left, PowerlineDirection::Right] {
+                    let items = [
+                        ContentItem::text("").bg(RED).pad(6),
+                        ContentItem::powerline(style, fill, direction)
+                            .fg(RED)
+                            .bg(BLUE),
+                        ContentItem::text("").bg(BLUE).pad(6),
+                    ];
+                    bar.clear_monitors();
+                    bar.draw(0, Alignment::Left, &items);
+                    assert_same_image(
+                        &format!("x11_{style:?}_{fill:?}_{direction:?}"),
+                        &bar.screenshot(0).await,
+                        &render(&items).await,
+                    );
+                }
+            }
+        }
+    }
+}